@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, Read as _, Seek as _, SeekFrom, Write as _};
+use std::path::{Path, PathBuf};
+
+/// Marks the end of an executable with a script bundled into it.
+const MAGIC: &[u8; 8] = b"OXBUNDLE";
+/// The length of the trailer following the bundled files: their length, and then `MAGIC`.
+const TRAILER_LEN: u64 = 16;
+
+/// A script, along with the modules it imports, packed into a copy of the interpreter.
+#[derive(Debug, Default)]
+pub struct Bundle {
+    /// The name of the script's file, used when reporting errors.
+    pub name: String,
+    pub source: String,
+    /// The source of each module, by the file name it's imported with.
+    pub modules: HashMap<PathBuf, String>,
+}
+
+impl Bundle {
+    /// Read the bundle appended to the running executable, if there is one.
+    /// An executable which can't be read to look for one is treated as having none,
+    /// so that the interpreter still starts; only a bundle found but unreadable is an error.
+    pub fn load() -> Result<Option<Bundle>> {
+        let found = env::current_exe()
+            .and_then(File::open)
+            .map_err(Error::from)
+            .and_then(|mut exe| Ok(payload_range(&mut exe)?.map(|range| (exe, range))));
+        let (mut exe, (start, len)) = match found {
+            Ok(Some(found)) => found,
+            Ok(None) | Err(Error::IO(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        exe.seek(SeekFrom::Start(start))?;
+        let mut payload = vec![0; len as usize];
+        exe.read_exact(&mut payload)?;
+        let mut reader = &payload[..];
+        let mut bundle = Bundle {
+            name: read_str(&mut reader)?,
+            source: read_str(&mut reader)?,
+            modules: HashMap::new(),
+        };
+        while !reader.is_empty() {
+            let file = read_str(&mut reader)?;
+            bundle.modules.insert(file.into(), read_str(&mut reader)?);
+        }
+        Ok(Some(bundle))
+    }
+
+    /// Write a copy of the running interpreter to `output`, with the bundle appended to it.
+    pub fn write(&self, output: &Path) -> Result<()> {
+        let exe = File::open(env::current_exe()?)?;
+        let mut payload = Vec::new();
+        write_str(&mut payload, &self.name);
+        write_str(&mut payload, &self.source);
+        let mut modules: Vec<_> = self.modules.iter().collect();
+        modules.sort();
+        for (file, source) in modules {
+            write_str(&mut payload, &file.to_string_lossy());
+            write_str(&mut payload, source);
+        }
+
+        let mut out = File::create(output)?;
+        io::copy(&mut &exe, &mut out)?;
+        out.write_all(&payload)?;
+        out.write_all(&(payload.len() as u64).to_le_bytes())?;
+        out.write_all(MAGIC)?;
+        let permissions = fs::metadata(env::current_exe()?)?.permissions();
+        fs::set_permissions(output, permissions)?;
+        Ok(())
+    }
+}
+
+/// The offset and length of the bundled files at the end of `exe`, if there are any.
+fn payload_range(exe: &mut File) -> Result<Option<(u64, u64)>> {
+    let exe_len = exe.metadata()?.len();
+    if exe_len < TRAILER_LEN {
+        return Ok(None);
+    }
+    exe.seek(SeekFrom::Start(exe_len - TRAILER_LEN))?;
+    let mut trailer = [0; TRAILER_LEN as usize];
+    exe.read_exact(&mut trailer)?;
+    if &trailer[8..] != MAGIC {
+        return Ok(None);
+    }
+    let len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    match (exe_len - TRAILER_LEN).checked_sub(len) {
+        Some(start) => Ok(Some((start, len))),
+        None => Err(Error::Corrupted),
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn read_str(input: &mut &[u8]) -> Result<String> {
+    let mut take = |len: usize| {
+        if input.len() < len {
+            return Err(Error::Corrupted);
+        }
+        let (bytes, rest) = input.split_at(len);
+        *input = rest;
+        Ok(bytes)
+    };
+    let len = u64::from_le_bytes(take(8)?.try_into().unwrap());
+    let bytes = take(len.try_into().map_err(|_| Error::Corrupted)?)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::Corrupted)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IO(io::Error),
+    /// The end of the executable looks like a bundle, but can't be read as one.
+    Corrupted,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::IO(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IO(err) => write!(f, "{}", err),
+            Error::Corrupted => write!(f, "The bundled script is corrupted"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO(err) => Some(err),
+            Error::Corrupted => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
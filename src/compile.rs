@@ -4,22 +4,77 @@ use std::iter::Peekable;
 use std::num::TryFromIntError;
 use std::rc::Rc;
 
+use crate::diag::Diagnostic;
 use crate::loc::{Locate, SourceLocation, TryLocate};
 use crate::scan::{self, Token, TokenType, TokenType::*};
-use crate::vm::{Instruction, Value};
+use crate::vm::{Chunk, Instruction, Value};
 
 struct VarDecl {
     name: String,
-    index: u16,
+    index: u32,
+    loc: Option<SourceLocation>,
+    /// Whether the variable's value is ever read.
+    used: bool,
+}
+
+/// A loop being compiled, which `break` can jump out of.
+struct Loop {
+    label: Option<String>,
+    /// How many values were on the stack when the loop started, which jumping
+    /// out of it or to its next iteration has to pop back down to.
+    height: usize,
+    /// Where `continue` jumps to, which starts the next iteration.
+    start: usize,
+    /// The jumps out of the loop, patched once its end is known.
+    breaks: Vec<usize>,
 }
 
 pub struct Compiler {
     locals: Vec<VarDecl>,
+    loops: Vec<Loop>,
+    max_locals: usize,
+    /// How many values the code compiled so far leaves in the frame, counting its locals
+    /// as well as the temporaries of the expressions still being evaluated.
+    depth: usize,
+    /// Whether the code is the body of a function, which `return` can leave.
+    in_function: bool,
+    /// How many parentheses or square brackets the code is inside of, within the current block.
+    /// Expressions in them carry on over newlines, since they can't end before the closing bracket.
+    brackets: usize,
+    /// Whether the code is the values of a switch case, where a colon after an identifier
+    /// ends the case instead of labelling a loop.
+    case_values: bool,
+    /// Where the statement being compiled starts, since `import * from` can only be one.
+    statement_start: Option<usize>,
+    /// Whether the code emitted last is an expression which always evaluates to a number,
+    /// so that arithmetic on it can skip checking its type.
+    numeric: bool,
+    /// Whether the code emitted last is a call which no jump skips,
+    /// so that the values it returns can be unpacked as they're returned.
+    call: bool,
     instrs: Vec<Instruction>,
+    benches: Vec<(String, Value)>,
+    diagnostics: Vec<Diagnostic>,
+    /// The globals declared with `global` or `fn` so far.
+    globals: Vec<String>,
+    /// Assignments to variables which weren't declared when they were compiled.
+    /// They're only reported if the variable isn't declared later on either.
+    implicit_globals: Vec<(String, SourceLocation)>,
 }
 
 type ScanResult = scan::Result<Token>;
 
+/// How far compilation had got at some point, so that code which failed to compile can be undone.
+pub struct Checkpoint {
+    locals: usize,
+    loops: usize,
+    instrs: usize,
+    benches: usize,
+    diagnostics: usize,
+    globals: usize,
+    implicit_globals: usize,
+}
+
 fn peek<I>(it: &mut Peekable<I>) -> Result<Option<&TokenType>>
 where
     I: Iterator<Item = ScanResult>,
@@ -43,40 +98,176 @@ impl Compiler {
         let vm_owned = VarDecl {
             name: String::new(),
             index: 0,
+            loc: None,
+            used: true,
         };
         Compiler {
             locals: vec![vm_owned],
+            loops: Vec::new(),
+            max_locals: 1,
+            depth: 1,
+            in_function: false,
+            brackets: 0,
+            case_values: false,
+            statement_start: None,
+            numeric: false,
+            call: false,
             instrs: Vec::new(),
+            benches: Vec::new(),
+            diagnostics: Vec::new(),
+            globals: Vec::new(),
+            implicit_globals: Vec::new(),
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            locals: self.locals.len(),
+            loops: self.loops.len(),
+            instrs: self.instrs.len(),
+            benches: self.benches.len(),
+            diagnostics: self.diagnostics.len(),
+            globals: self.globals.len(),
+            implicit_globals: self.implicit_globals.len(),
         }
     }
 
+    /// Forget everything compiled since `checkpoint` was taken.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.locals.truncate(checkpoint.locals);
+        self.loops.truncate(checkpoint.loops);
+        self.instrs.truncate(checkpoint.instrs);
+        self.benches.truncate(checkpoint.benches);
+        self.diagnostics.truncate(checkpoint.diagnostics);
+        self.globals.truncate(checkpoint.globals);
+        self.implicit_globals.truncate(checkpoint.implicit_globals);
+        self.brackets = 0;
+        self.case_values = false;
+    }
+
     pub fn instructions(&mut self) -> Vec<Instruction> {
         let mut chunk = Vec::new();
         std::mem::swap(&mut chunk, &mut self.instrs);
         chunk
     }
 
+    /// Take the benchmarks declared by `bench` blocks compiled so far,
+    /// each as a name and a zero-argument function running the block.
+    pub fn benches(&mut self) -> Vec<(String, Value)> {
+        std::mem::take(&mut self.benches)
+    }
+
+    /// Take the warnings found in the code compiled so far.
+    pub fn diagnostics(&mut self) -> Vec<Diagnostic> {
+        for (name, loc) in std::mem::take(&mut self.implicit_globals) {
+            if !self.globals.contains(&name) {
+                let message = format!(
+                    "Assignment to undeclared variable '{}' creates a global",
+                    name
+                );
+                let diag = Diagnostic::warning("implicit-global", message, loc);
+                self.diagnostics.push(diag);
+            }
+        }
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Take over what a function or benchmark compiled by `other` declared and found.
+    fn absorb(&mut self, other: &mut Compiler) {
+        self.benches.append(&mut other.benches);
+        self.diagnostics.append(&mut other.diagnostics);
+        self.globals.append(&mut other.globals);
+        self.implicit_globals.append(&mut other.implicit_globals);
+    }
+
     fn emit(&mut self, instr: Instruction) {
+        self.numeric = false;
+        self.call = false;
+        // Jumps leave the stack as it is, and the placeholders for them are counted as such.
+        // Code following an unconditional jump sets the depth itself.
+        let effect = instr.stack_effect().unwrap_or(0);
+        self.depth = (self.depth as isize + effect) as usize;
         self.instrs.push(instr);
     }
 
-    fn declare_local(&mut self, name: String, loc: SourceLocation) -> Result<u16> {
-        let index: u16 = self
-            .locals
-            .len()
+    /// Emit an instruction which evaluates to a number if its operands do.
+    fn emit_arithmetic(&mut self, instr: Instruction, operands_numeric: bool) {
+        self.emit(instr);
+        self.numeric = operands_numeric;
+    }
+
+    /// Declare a local variable holding the value on top of the stack.
+    fn declare_local(&mut self, name: String, loc: SourceLocation) -> Result<u32> {
+        self.declare_local_at(self.depth - 1, name, loc)
+    }
+
+    /// Declare a local variable holding the value in the given slot of the frame.
+    /// Locals declared in the middle of an expression come after its temporaries.
+    fn declare_local_at(&mut self, slot: usize, name: String, loc: SourceLocation) -> Result<u32> {
+        let index: u32 = slot
             .try_into()
             .map_err(|cause| Error::Conversion { cause, loc })?;
-        self.locals.push(VarDecl { name, index });
+        // Slots are reused once the scope declaring them is closed,
+        // so the frame only needs to fit the locals alive at the same time.
+        self.locals.push(VarDecl {
+            name,
+            index,
+            loc: Some(loc),
+            used: false,
+        });
+        self.max_locals = self.max_locals.max(slot + 1);
         Ok(index)
     }
 
-    fn find_local(&self, name: &str) -> Option<u16> {
+    fn find_local(&self, name: &str) -> Option<u32> {
         self.locals
             .iter()
             .rfind(|decl| decl.name == name)
             .map(|decl| decl.index)
     }
 
+    /// Find the local variable `name` in order to read it.
+    fn use_local(&mut self, name: &str) -> Option<u32> {
+        let decl = self.locals.iter_mut().rfind(|decl| decl.name == name)?;
+        decl.used = true;
+        Some(decl.index)
+    }
+
+    /// Peek at the token which could continue the current expression.
+    /// Outside of brackets, a token which might also start an expression of its own
+    /// (`-`, `(` or `[`) only does so if it is on the same line,
+    /// and is otherwise treated as the end of the expression.
+    fn peek_continuation<'a, I>(&self, it: &'a mut Peekable<I>) -> Result<Option<&'a TokenType>>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let ends_expression = match it.peek() {
+            Some(Ok(t)) => {
+                self.brackets == 0
+                    && t.starts_line
+                    && matches!(t.ttype, Minus | LeftParen | LeftSquare)
+            }
+            _ => false,
+        };
+        if ends_expression {
+            Ok(None)
+        } else {
+            peek(it)
+        }
+    }
+
+    /// Compile `f` as if inside `brackets` levels of brackets, restoring the level afterwards.
+    fn with_brackets<T>(
+        &mut self,
+        brackets: usize,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let outer = std::mem::replace(&mut self.brackets, brackets);
+        let result = f(self);
+        self.brackets = outer;
+        result
+    }
+
     fn stub_jump(&mut self) -> usize {
         let idx = self.instrs.len();
         self.emit(Instruction::Temp);
@@ -87,30 +278,56 @@ impl Compiler {
         &mut self,
         src: usize,
         dst: usize,
-        f: impl FnOnce(i16) -> Instruction,
+        f: impl FnOnce(i32) -> Instruction,
     ) -> Result<()> {
         let offset = (dst - src)
             .try_into()
             .expect("Loop code too big to fit into VM register");
         self.instrs[src] = f(offset);
+        // Whatever was emitted last, the jump may skip it.
+        self.numeric = false;
+        self.call = false;
         Ok(())
     }
 
+    /// Pop `count` values from beneath the one on top of the stack.
+    fn pop_under_top(&mut self, count: usize) {
+        match count {
+            0 => (),
+            // A single value is cheaper to drop by swapping it to the top.
+            1 => {
+                self.emit(Instruction::Swap);
+                self.emit(Instruction::Pop);
+            }
+            _ => {
+                self.emit(Instruction::SaveReturn);
+                for _ in 0..count {
+                    self.emit(Instruction::Pop);
+                }
+                self.emit(Instruction::RestoreReturn);
+            }
+        }
+    }
+
     fn close_scope(&mut self, num_locals: usize) {
-        self.emit(Instruction::SaveReturn);
         let final_len = self.locals.len().saturating_sub(num_locals);
-        for _ in 0..num_locals {
-            self.emit(Instruction::Pop);
+        self.pop_under_top(num_locals);
+        for decl in self.locals.drain(final_len..) {
+            if let (false, Some(loc)) = (decl.used || decl.name.starts_with('_'), decl.loc) {
+                let message = format!("Unused variable '{}'", decl.name);
+                let diag = Diagnostic::warning("unused-variable", message, loc);
+                self.diagnostics.push(diag);
+            }
         }
-        self.emit(Instruction::RestoreReturn);
-        self.locals.truncate(final_len);
     }
 
     pub fn program<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
     where
         I: Iterator<Item = ScanResult>,
     {
-        while let Some(_) = peek(it)? {
+        // Whatever an earlier program evaluated to is gone by the time this one runs.
+        self.depth = self.locals.len();
+        while peek(it)?.is_some() {
             self.declaration(it)?;
             if peek(it)?.is_some() {
                 self.emit(Instruction::Pop);
@@ -123,9 +340,14 @@ impl Compiler {
     where
         I: Iterator<Item = ScanResult>,
     {
+        self.statement_start = match it.peek() {
+            Some(Ok(token)) => Some(token.loc.offset),
+            _ => None,
+        };
         match peek(it)? {
             Some(Let) => self.local(it),
             Some(Global) => self.global(it),
+            Some(Function) => self.fn_declaration(it),
             _ => self.expression(it),
         }
     }
@@ -172,12 +394,16 @@ impl Compiler {
         I: Iterator<Item = ScanResult>,
     {
         self.comparison(it)?;
-        while let Some(EqualEqual) | Some(BangEqual) = peek(it)? {
+        while let Some(EqualEqual) | Some(BangEqual) | Some(Is) = peek(it)? {
             let op = advance(it)?;
             self.comparison(it)?;
-            self.emit(Instruction::Equal);
-            if let BangEqual = op.ttype {
-                self.emit(Instruction::Not);
+            match op.ttype {
+                Is => self.emit(Instruction::Is),
+                BangEqual => {
+                    self.emit(Instruction::Equal);
+                    self.emit(Instruction::Not);
+                }
+                _ => self.emit(Instruction::Equal),
             }
         }
         Ok(())
@@ -187,20 +413,25 @@ impl Compiler {
     where
         I: Iterator<Item = ScanResult>,
     {
-        self.addition(it)?;
+        self.union(it)?;
         loop {
             match peek(it)? {
                 Some(Less) | Some(GreaterEqual) => {
+                    let lhs_numeric = self.numeric;
                     let op = advance(it)?;
-                    self.addition(it)?;
-                    self.emit(Instruction::Less);
+                    self.union(it)?;
+                    if lhs_numeric && self.numeric {
+                        self.emit(Instruction::LessNum);
+                    } else {
+                        self.emit(Instruction::Less);
+                    }
                     if let GreaterEqual = op.ttype {
                         self.emit(Instruction::Not);
                     }
                 }
                 Some(Greater) | Some(LessEqual) => {
                     let op = advance(it)?;
-                    self.addition(it)?;
+                    self.union(it)?;
                     self.emit(Instruction::Greater);
                     if let LessEqual = op.ttype {
                         self.emit(Instruction::Not);
@@ -212,17 +443,47 @@ impl Compiler {
         Ok(())
     }
 
+    fn union<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        self.intersection(it)?;
+        while let Some(Pipe) = peek(it)? {
+            advance(it)?;
+            self.intersection(it)?;
+            self.emit(Instruction::BitOr);
+        }
+        Ok(())
+    }
+
+    fn intersection<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        self.addition(it)?;
+        while let Some(Ampersand) = peek(it)? {
+            advance(it)?;
+            self.addition(it)?;
+            self.emit(Instruction::BitAnd);
+        }
+        Ok(())
+    }
+
     fn addition<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
     where
         I: Iterator<Item = ScanResult>,
     {
         self.multiplication(it)?;
-        while let Some(Plus) | Some(Minus) = peek(it)? {
+        while let Some(Plus) | Some(Minus) = self.peek_continuation(it)? {
+            let lhs_numeric = self.numeric;
             let op = advance(it)?;
             self.multiplication(it)?;
-            match op.ttype {
-                Plus => self.emit(Instruction::Add),
-                Minus => self.emit(Instruction::Sub),
+            let numeric = lhs_numeric && self.numeric;
+            match (op.ttype, numeric) {
+                (Plus, true) => self.emit_arithmetic(Instruction::AddNum, true),
+                (Minus, true) => self.emit_arithmetic(Instruction::SubNum, true),
+                (Plus, false) => self.emit(Instruction::Add),
+                (Minus, false) => self.emit(Instruction::Sub),
                 _ => unreachable!(),
             }
         }
@@ -235,11 +496,13 @@ impl Compiler {
     {
         self.unary(it)?;
         while let Some(Star) | Some(Slash) = peek(it)? {
+            let lhs_numeric = self.numeric;
             let op = advance(it)?;
             self.unary(it)?;
+            let numeric = lhs_numeric && self.numeric;
             match op.ttype {
-                Star => self.emit(Instruction::Mul),
-                Slash => self.emit(Instruction::Div),
+                Star => self.emit_arithmetic(Instruction::Mul, numeric),
+                Slash => self.emit_arithmetic(Instruction::Div, numeric),
                 _ => unreachable!(),
             }
         }
@@ -254,7 +517,7 @@ impl Compiler {
             Some(Minus) => {
                 advance(it)?;
                 self.unary(it)?;
-                self.emit(Instruction::Neg);
+                self.emit_arithmetic(Instruction::Neg, self.numeric);
             }
             Some(Not) | Some(Bang) => {
                 advance(it)?;
@@ -271,9 +534,70 @@ impl Compiler {
         I: Iterator<Item = ScanResult>,
     {
         self.primary(it)?;
-        while let Some(LeftParen) = peek(it)? {
-            let argc = self.args(it)?;
-            self.emit(Instruction::Call(argc));
+        self.postfix(it)
+    }
+
+    /// Calls, indexing, field access and `?` following a value already on the stack.
+    fn postfix<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        loop {
+            match self.peek_continuation(it)? {
+                Some(LeftParen) => {
+                    let argc = self.with_brackets(self.brackets + 1, |this| this.args(it))?;
+                    self.emit(Instruction::Call(argc));
+                    self.call = true;
+                }
+                Some(LeftSquare) => {
+                    advance(it)?;
+                    self.with_brackets(self.brackets + 1, |this| this.expression(it))?;
+                    let found = advance(it)?;
+                    if !matches!(found.ttype, RightSquare) {
+                        let expected = vec![RightSquare];
+                        return Err(Error::Mismatch {
+                            expected,
+                            found: found.into(),
+                        });
+                    }
+                    self.index_or_assign(it)?;
+                }
+                Some(Dot) => {
+                    advance(it)?;
+                    let found = advance(it)?;
+                    if let Identifier(name) = found.ttype {
+                        self.emit(Instruction::Push(Value::new_str(name)));
+                        self.index_or_assign(it)?;
+                    } else {
+                        let expected = vec![Identifier(String::new())];
+                        return Err(Error::Mismatch {
+                            expected,
+                            found: found.into(),
+                        });
+                    }
+                }
+                Some(Question) => {
+                    advance(it)?;
+                    self.emit(Instruction::Try);
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the element at the index on top of the stack,
+    /// or store the value of an `=` following it there.
+    fn index_or_assign<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        if let Some(Equal) = peek(it)? {
+            advance(it)?;
+            self.expression(it)?;
+            self.emit(Instruction::SetIndex);
+        } else {
+            self.emit(Instruction::Index);
         }
         Ok(())
     }
@@ -284,16 +608,24 @@ impl Compiler {
     {
         let token = peek(it)?.ok_or(Error::EndOfInput)?;
         match token {
-            LeftParen => self.grouping(it),
+            LeftParen => self.with_brackets(self.brackets + 1, |this| this.grouping(it)),
             LeftBracket => self.block(it),
+            LeftSquare => self.with_brackets(self.brackets + 1, |this| this.array(it)),
             If => self.if_expr(it),
-            While => self.while_expr(it),
+            While => self.while_expr(None, it),
+            For => self.for_expr(None, it),
+            Switch => self.with_brackets(0, |this| this.switch_expr(it)),
+            Break => self.break_expr(it),
+            Continue => self.continue_expr(it),
+            Return => self.return_expr(it),
             Function => self.fn_expr(it),
+            Bench => self.bench_expr(it),
             Identifier(_) => self.variable(it),
             Literal(_) => {
                 let token = advance(it)?;
                 if let Literal(x) = token.ttype {
-                    self.emit(Instruction::Push(x));
+                    let numeric = matches!(x, Value::Num(_) | Value::Int(_));
+                    self.emit_arithmetic(Instruction::Push(x), numeric);
                     Ok(())
                 } else {
                     unreachable!()
@@ -303,14 +635,24 @@ impl Compiler {
                 let expected = vec![
                     LeftParen,
                     LeftBracket,
+                    LeftSquare,
                     If,
+                    Switch,
                     While,
+                    For,
+                    Break,
+                    Continue,
+                    Return,
                     Function,
+                    Bench,
                     Identifier(String::new()),
                     Literal(Value::Null),
                 ];
                 let found = advance(it)?;
-                Err(Error::Mismatch { expected, found })
+                Err(Error::Mismatch {
+                    expected,
+                    found: found.into(),
+                })
             }
         }
     }
@@ -323,15 +665,25 @@ impl Compiler {
         self.expression(it)?;
         let found = advance(it)?;
         if let RightParen = found.ttype {
-            advance(it)?;
             Ok(())
         } else {
             let expected = vec![RightParen];
-            Err(Error::Mismatch { expected, found })
+            Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            })
         }
     }
 
+    /// A block, in which newlines end expressions again even if it's inside brackets.
     fn block<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        self.with_brackets(0, |this| this.block_contents(it))
+    }
+
+    fn block_contents<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
     where
         I: Iterator<Item = ScanResult>,
     {
@@ -357,30 +709,99 @@ impl Compiler {
         Ok(())
     }
 
+    fn array<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let start = advance(it)?; // Skip LeftSquare
+        let mut len = 0usize;
+        if let Some(RightSquare) = peek(it)? {
+            advance(it)?;
+        } else {
+            loop {
+                self.expression(it)?;
+                len += 1;
+                let found = advance(it)?;
+                match found.ttype {
+                    Comma => continue,
+                    RightSquare => break,
+                    _ => {
+                        let expected = vec![RightSquare, Comma];
+                        return Err(Error::Mismatch {
+                            expected,
+                            found: found.into(),
+                        });
+                    }
+                }
+            }
+        }
+        let len = len.try_into().map_err(|cause| Error::Conversion {
+            cause,
+            loc: start.loc,
+        })?;
+        self.emit(Instruction::MakeArray(len));
+        Ok(())
+    }
+
     fn local<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
     where
         I: Iterator<Item = ScanResult>,
     {
         advance(it)?; // Skip Let
-        let found = advance(it)?;
-        if let Identifier(ident) = found.ttype {
-            let next = advance(it)?;
-            if let Equal = next.ttype {
-                self.expression(it)?;
-                let idx = self.declare_local(ident, found.loc)?;
-                self.emit(Instruction::GetLocal(idx));
-                Ok(())
+        let mut names = Vec::new();
+        loop {
+            let found = advance(it)?;
+            if let Identifier(ident) = found.ttype {
+                names.push((ident, found.loc));
             } else {
-                let expected = vec![Equal];
-                Err(Error::Mismatch {
+                let expected = vec![Identifier(String::new())];
+                return Err(Error::Mismatch {
                     expected,
-                    found: next,
-                })
+                    found: found.into(),
+                });
+            }
+            if let Some(Comma) = peek(it)? {
+                advance(it)?;
+            } else {
+                break;
             }
+        }
+        let next = advance(it)?;
+        if let Equal = next.ttype {
+            self.expression(it)?;
         } else {
-            let expected = vec![Identifier(String::new())];
-            Err(Error::Mismatch { expected, found })
+            let expected = vec![Equal, Comma];
+            return Err(Error::Mismatch {
+                expected,
+                found: next.into(),
+            });
+        }
+        if let [(ident, loc)] = &names[..] {
+            let idx = self.declare_local(ident.clone(), *loc)?;
+            self.emit(Instruction::GetLocal(idx));
+            return Ok(());
+        }
+        // Declaring several variables at once unpacks the elements of an array into them,
+        // or the values a function returns, which then sit on the stack in the same order
+        // as the variables.
+        let (_, loc) = names[0];
+        let count = names
+            .len()
+            .try_into()
+            .map_err(|cause| Error::Conversion { cause, loc })?;
+        if let (true, Some(&Instruction::Call(argc))) = (self.call, self.instrs.last()) {
+            self.instrs.pop();
+            self.depth += usize::from(argc);
+            self.emit(Instruction::CallUnpack(argc, count));
+        } else {
+            self.emit(Instruction::Unpack(count));
+        }
+        let first = self.depth - names.len();
+        for (i, (ident, loc)) in names.into_iter().enumerate() {
+            self.declare_local_at(first + i, ident, loc)?;
         }
+        self.emit(Instruction::Push(Value::Null));
+        Ok(())
     }
 
     fn global<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
@@ -393,15 +814,22 @@ impl Compiler {
             let found = advance(it)?;
             if let Equal = found.ttype {
                 self.expression(it)?;
-                self.emit(Instruction::SetGlobal(ident));
+                self.globals.push(ident.clone());
+                self.emit(Instruction::DefineGlobal(ident));
                 Ok(())
             } else {
                 let expected = vec![Equal];
-                Err(Error::Mismatch { expected, found })
+                Err(Error::Mismatch {
+                    expected,
+                    found: found.into(),
+                })
             }
         } else {
             let expected = vec![Identifier(String::new())];
-            Err(Error::Mismatch { expected, found })
+            Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            })
         }
     }
 
@@ -418,12 +846,38 @@ impl Compiler {
                 if let Some(idx) = self.find_local(&ident) {
                     self.emit(Instruction::SetLocal(idx));
                 } else {
+                    if !self.globals.contains(&ident) {
+                        self.implicit_globals.push((ident.clone(), token.loc));
+                    }
                     self.emit(Instruction::SetGlobal(ident));
                 }
                 Ok(())
             }
+            (Identifier(label), Some(Colon)) if !self.case_values => {
+                advance(it)?;
+                match peek(it)? {
+                    Some(While) => self.while_expr(Some(label), it),
+                    Some(For) => self.for_expr(Some(label), it),
+                    _ => {
+                        let expected = vec![While, For];
+                        let found = advance(it)?;
+                        Err(Error::Mismatch {
+                            expected,
+                            found: found.into(),
+                        })
+                    }
+                }
+            }
+            (Identifier(ident), Some(Star))
+                if ident == "import" && self.find_local(&ident).is_none() =>
+            {
+                if self.statement_start != Some(token.loc.offset) {
+                    return Err(Error::MisplacedImport(token.loc));
+                }
+                self.import_all(it)
+            }
             (Identifier(ident), _) => {
-                if let Some(idx) = self.find_local(&ident) {
+                if let Some(idx) = self.use_local(&ident) {
                     self.emit(Instruction::GetLocal(idx));
                 } else {
                     self.emit(Instruction::GetGlobal(ident));
@@ -434,43 +888,245 @@ impl Compiler {
         }
     }
 
+    /// `import * from namespace`, which declares every entry of a map as a global.
+    fn import_all<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        advance(it)?; // Skip Star
+        let found = advance(it)?;
+        match found.ttype {
+            Identifier(ident) if ident == "from" => {
+                self.expression(it)?;
+                self.emit(Instruction::ImportAll);
+                Ok(())
+            }
+            _ => {
+                let expected = vec![Identifier("from".to_owned())];
+                Err(Error::Mismatch {
+                    expected,
+                    found: found.into(),
+                })
+            }
+        }
+    }
+
     fn if_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
     where
         I: Iterator<Item = ScanResult>,
     {
         advance(it)?; // Skip If
-        self.expression(it)?; // Condition
-        let jump_idx = self.stub_jump();
-        self.emit(Instruction::Pop);
-        let token = peek(it)?.ok_or(Error::EndOfInput)?;
-        match token {
-            Then => {
+
+        // The jumps to the end of the whole chain of `else if`s, taken after each branch.
+        let mut end_jumps = Vec::new();
+        loop {
+            self.expression(it)?; // Condition
+            let jump_idx = self.stub_jump();
+            self.emit(Instruction::Pop);
+            self.branch(it)?;
+            let jump_else_idx = self.stub_jump();
+            end_jumps.push(jump_else_idx);
+            self.patch_jump(jump_idx, jump_else_idx, Instruction::JumpIfFalse)?;
+            self.emit(Instruction::Pop);
+            if let Some(Else) = peek(it)? {
                 advance(it)?;
+                if let Some(If) = peek(it)? {
+                    advance(it)?;
+                    continue;
+                }
                 self.expression(it)?;
+            } else {
+                self.emit(Instruction::Push(Value::Null));
             }
-            LeftBracket => {
-                self.block(it)?;
+            break;
+        }
+        for jump_idx in end_jumps {
+            self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::Jump)?;
+        }
+        Ok(())
+    }
+
+    /// The code run when a condition holds: either `then` followed by an expression, or a block.
+    fn branch<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        match peek(it)?.ok_or(Error::EndOfInput)? {
+            Then => {
+                advance(it)?;
+                self.expression(it)
             }
+            LeftBracket => self.block(it),
             _ => {
                 let expected = vec![Then, LeftBracket];
                 let found = advance(it)?;
-                return Err(Error::Mismatch { expected, found });
+                Err(Error::Mismatch {
+                    expected,
+                    found: found.into(),
+                })
             }
-        };
-        let jump_else_idx = self.stub_jump();
+        }
+    }
+
+    /// A switch compares a value against those listed by each of its cases in turn,
+    /// and evaluates to the body of the first case with an equal value, or else of `default`.
+    /// A body ends where the next case starts, unless it ends with `fallthrough`,
+    /// which carries on into the next body without comparing its values.
+    fn switch_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        advance(it)?; // Skip Switch
+
+        // The value being switched on stays on the stack while the cases are compared to it,
+        // and is popped before running a body, so that bodies can declare their own locals.
+        self.expression(it)?;
+        let found = advance(it)?;
+        if !matches!(found.ttype, LeftBracket) {
+            let expected = vec![LeftBracket];
+            return Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            });
+        }
+        let mut end_jumps = Vec::new();
+        // The jump out of the previous body, if it falls through to the next one.
+        let mut fallthrough = None;
+        while let Some(Case) = peek(it)? {
+            advance(it)?;
+            // Any of the listed values selects the case, short-circuiting like `or`.
+            let mut value_jumps = Vec::new();
+            let outer_values = std::mem::replace(&mut self.case_values, true);
+            loop {
+                self.emit(Instruction::Dup);
+                self.expression(it)?;
+                self.emit(Instruction::Equal);
+                if let Some(Comma) = peek(it)? {
+                    advance(it)?;
+                    value_jumps.push(self.stub_jump());
+                    self.emit(Instruction::Pop);
+                } else {
+                    break;
+                }
+            }
+            self.case_values = outer_values;
+            self.case_colon(it)?;
+            for jump_idx in value_jumps {
+                self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::JumpIfTrue)?;
+            }
+            let next_case_idx = self.stub_jump();
+            // The next case starts with the comparison and the value being switched on.
+            let next_case_depth = self.depth;
+            // Pop the comparison, and the value being switched on
+            self.emit(Instruction::Pop);
+            self.emit(Instruction::Pop);
+            if let Some(jump_idx) = fallthrough.take() {
+                self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::Jump)?;
+            }
+            match self.case_body(it)? {
+                Some(jump_idx) => fallthrough = Some(jump_idx),
+                None => end_jumps.push(self.stub_jump()),
+            }
+            self.depth = next_case_depth;
+            self.patch_jump(
+                next_case_idx,
+                self.instrs.len() - 1,
+                Instruction::JumpIfFalse,
+            )?;
+            self.emit(Instruction::Pop);
+        }
+        // None of the cases matched, so the value being switched on is still there
         self.emit(Instruction::Pop);
-        if let Some(Else) = peek(it)? {
+        if let Some(jump_idx) = fallthrough {
+            self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::Jump)?;
+        }
+        if let Some(Default) = peek(it)? {
             advance(it)?;
-            self.expression(it)?;
+            self.case_colon(it)?;
+            // Nothing can follow the default case, so its body never falls through.
+            self.case_body(it)?;
         } else {
             self.emit(Instruction::Push(Value::Null));
         }
-        self.patch_jump(jump_else_idx, self.instrs.len() - 1, Instruction::Jump)?;
-        self.patch_jump(jump_idx, jump_else_idx, Instruction::JumpIfFalse)?;
+        let found = advance(it)?;
+        if !matches!(found.ttype, RightBracket) {
+            let expected = vec![Case, Default, RightBracket];
+            return Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            });
+        }
+        for jump_idx in end_jumps {
+            self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::Jump)?;
+        }
         Ok(())
     }
 
-    fn while_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    fn case_colon<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let found = advance(it)?;
+        if matches!(found.ttype, Colon) {
+            Ok(())
+        } else {
+            let expected = vec![Comma, Colon];
+            Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            })
+        }
+    }
+
+    /// The declarations of a switch case up to the next case, default or the end of the switch,
+    /// in a scope of their own. An empty body evaluates to null.
+    /// Returns the jump to patch to the start of the next body if the body ends with `fallthrough`,
+    /// in which case its value is popped instead.
+    fn case_body<I>(&mut self, it: &mut Peekable<I>) -> Result<Option<usize>>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let frame_start = self.locals.len();
+        let mut empty = true;
+        let mut falls_through = false;
+        loop {
+            match peek(it)? {
+                Some(Case) | Some(Default) | Some(RightBracket) => break,
+                Some(Fallthrough) => {
+                    advance(it)?;
+                    if let Some(Case) | Some(Default) = peek(it)? {
+                        falls_through = true;
+                        break;
+                    }
+                    let expected = vec![Case, Default];
+                    let found = advance(it)?;
+                    return Err(Error::Mismatch {
+                        expected,
+                        found: found.into(),
+                    });
+                }
+                _ => (),
+            }
+            if !empty {
+                self.emit(Instruction::Pop);
+            }
+            self.declaration(it)?;
+            empty = false;
+        }
+        if empty {
+            self.emit(Instruction::Push(Value::Null));
+        }
+        self.close_scope(self.locals.len() - frame_start);
+        if falls_through {
+            self.emit(Instruction::Pop);
+            Ok(Some(self.stub_jump()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// A while loop, optionally labelled so that `break` can name it from inside nested loops.
+    fn while_expr<I>(&mut self, label: Option<String>, it: &mut Peekable<I>) -> Result<()>
     where
         I: Iterator<Item = ScanResult>,
     {
@@ -478,6 +1134,9 @@ impl Compiler {
         self.emit(Instruction::Push(Value::Null));
         let loop_idx = self.instrs.len();
         self.expression(it)?; // Condition
+
+        // Leaving the loop, the last iteration's value and the condition are on the stack.
+        let exit_depth = self.depth;
         let jump_idx = self.stub_jump();
         // Pop the condition value (If jump not taken)
         self.emit(Instruction::Pop);
@@ -485,27 +1144,263 @@ impl Compiler {
         // Pop last iteration's value
         self.emit(Instruction::Pop);
         if let LeftBracket = peek(it)?.ok_or(Error::EndOfInput)? {
+            // Continuing starts over from the null pushed in place of the body's value.
+            self.loops.push(Loop {
+                label,
+                height: self.depth,
+                start: loop_idx - 1,
+                breaks: Vec::new(),
+            });
             self.block(it)?;
         } else {
             let expected = vec![LeftBracket];
             let found = advance(it)?;
-            return Err(Error::Mismatch { expected, found });
+            return Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            });
         }
-        let loop_len: i16 = (self.instrs.len() - (loop_idx - 1))
+        let loop_len: i32 = (self.instrs.len() - (loop_idx - 1))
             .try_into()
             .expect("Loop code too big to fit into VM register");
         self.emit(Instruction::Jump(-loop_len));
+        self.depth = exit_depth;
         self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::JumpIfFalse)?;
         // Pop the condition value (If jump taken)
         self.emit(Instruction::Pop);
+        let body = self.loops.pop().expect("loop pushed before its body");
+        for jump_idx in body.breaks {
+            self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::Jump)?;
+        }
         Ok(())
     }
 
+    /// A loop running its body once for each element of an array, character of a string,
+    /// or key of a map, which evaluates to null.
+    fn for_expr<I>(&mut self, label: Option<String>, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let start = advance(it)?; // Skip For
+        let found = advance(it)?;
+        let (name, loc) = match found.ttype {
+            Identifier(name) => (name, found.loc),
+            _ => {
+                let expected = vec![Identifier(String::new())];
+                return Err(Error::Mismatch {
+                    expected,
+                    found: found.into(),
+                });
+            }
+        };
+        let found = advance(it)?;
+        if !matches!(found.ttype, In) {
+            let expected = vec![In];
+            return Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            });
+        }
+        self.expression(it)?;
+        // The elements and the position of the next one are kept in locals named after keywords,
+        // so that scripts can't refer to them.
+        self.emit(Instruction::IntoIter);
+        let elements = self.declare_local(String::from("for"), start.loc)?;
+        self.emit(Instruction::Push(Value::Num(0.0)));
+        self.declare_local(String::from("in"), start.loc)?;
+        for decl in self.locals.iter_mut().rev().take(2) {
+            decl.loc = None;
+        }
+
+        let loop_idx = self.instrs.len();
+        self.emit(Instruction::Next(elements));
+        // Leaving the loop, the null standing in for an element and false are on the stack.
+        let exit_depth = self.depth;
+        let jump_idx = self.stub_jump();
+        self.emit(Instruction::Pop);
+        // Both the next iteration and the code after the loop start without the current element.
+        self.loops.push(Loop {
+            label,
+            height: self.depth - 1,
+            start: loop_idx,
+            breaks: Vec::new(),
+        });
+        self.declare_local(name, loc)?;
+        if let LeftBracket = peek(it)?.ok_or(Error::EndOfInput)? {
+            self.block(it)?;
+        } else {
+            let expected = vec![LeftBracket];
+            let found = advance(it)?;
+            return Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            });
+        }
+        self.close_scope(1);
+        self.emit(Instruction::Pop);
+        let loop_len: i32 = (self.instrs.len() - (loop_idx - 1))
+            .try_into()
+            .expect("Loop code too big to fit into VM register");
+        self.emit(Instruction::Jump(-loop_len));
+        self.depth = exit_depth;
+        self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::JumpIfFalse)?;
+        // Pop the condition, and the null pushed in place of an element
+        self.emit(Instruction::Pop);
+        self.emit(Instruction::Pop);
+        self.emit(Instruction::Push(Value::Null));
+        let body = self.loops.pop().expect("loop pushed before its body");
+        for jump_idx in body.breaks {
+            self.patch_jump(jump_idx, self.instrs.len() - 1, Instruction::Jump)?;
+        }
+        self.close_scope(2);
+        Ok(())
+    }
+
+    /// Leave the innermost loop, or the one with the given label, which then evaluates to null.
+    fn break_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let start = advance(it)?; // Skip Break
+        let target = self
+            .jump_target(it)?
+            .ok_or(Error::BreakOutsideLoop(start.loc))?;
+        let depth = self.depth;
+        self.pop_to_loop(target);
+        self.emit(Instruction::Push(Value::Null));
+        let jump_idx = self.stub_jump();
+        self.loops[target].breaks.push(jump_idx);
+        // Like any other expression, as far as the code around it is concerned.
+        self.depth = depth + 1;
+        Ok(())
+    }
+
+    /// Skip the rest of the innermost loop's body, or the one with the given label,
+    /// going on with its next iteration.
+    fn continue_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let start = advance(it)?; // Skip Continue
+        let target = self
+            .jump_target(it)?
+            .ok_or(Error::ContinueOutsideLoop(start.loc))?;
+        let depth = self.depth;
+        self.pop_to_loop(target);
+        let offset: i32 = (self.instrs.len() + 1 - self.loops[target].start)
+            .try_into()
+            .expect("Loop code too big to fit into VM register");
+        self.emit(Instruction::Jump(-offset));
+        self.depth = depth + 1;
+        Ok(())
+    }
+
+    /// Leave the function early, returning the value following `return`,
+    /// or null if there's nothing else on the line. Several values separated by commas
+    /// are returned together, for the caller to unpack.
+    fn return_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let start = advance(it)?; // Skip Return
+        if !self.in_function {
+            return Err(Error::ReturnOutsideFunction(start.loc));
+        }
+        let depth = self.depth;
+        match it.peek() {
+            None
+            | Some(Ok(Token {
+                starts_line: true, ..
+            }))
+            | Some(Ok(Token {
+                ttype: RightBracket | Else,
+                ..
+            })) => self.emit(Instruction::Push(Value::Null)),
+            _ => self.expression(it)?,
+        }
+        let mut count = 1;
+        while let Some(Comma) = peek(it)? {
+            advance(it)?;
+            self.expression(it)?;
+            count += 1;
+        }
+        // Ret discards the locals, along with whatever the expression around
+        // the return had pushed so far.
+        if count == 1 {
+            self.emit(Instruction::Ret);
+        } else {
+            let count = count.try_into().map_err(|cause| Error::Conversion {
+                cause,
+                loc: start.loc,
+            })?;
+            self.emit(Instruction::RetMany(count));
+        }
+        self.depth = depth + 1;
+        Ok(())
+    }
+
+    /// The loop a `break` or `continue` applies to: the one named by the label following it,
+    /// or the innermost one. Returns None if there's no loop at all.
+    fn jump_target<I>(&mut self, it: &mut Peekable<I>) -> Result<Option<usize>>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        // A label has to be on the same line, or it would be the start of the next expression.
+        match it.peek() {
+            Some(Ok(Token {
+                ttype: Identifier(label),
+                starts_line: false,
+                ..
+            })) => {
+                let label = label.clone();
+                let loc = advance(it)?.loc;
+                self.loops
+                    .iter()
+                    .rposition(|l| l.label.as_ref() == Some(&label))
+                    .map(Some)
+                    .ok_or(Error::UnknownLabel { label, loc })
+            }
+            _ => Ok(self.loops.len().checked_sub(1)),
+        }
+    }
+
+    /// Pop the locals declared inside a loop before jumping out of it, as they would
+    /// otherwise be popped at the end of their scope, along with the temporaries
+    /// of any expression the jump is in the middle of.
+    fn pop_to_loop(&mut self, target: usize) {
+        for _ in self.loops[target].height..self.depth {
+            self.emit(Instruction::Pop);
+        }
+    }
+
+    /// A function starting a declaration, which is stored in the global of the same name.
+    /// It can also be called straight away, like `fn() { ... }()`.
+    fn fn_declaration<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        if let Some(name) = self.fn_value(it)? {
+            self.globals.push(name.clone());
+            self.emit(Instruction::DefineGlobal(name));
+        }
+        self.postfix(it)
+    }
+
+    /// A function anywhere else, which is only a value. Its name is kept for error messages.
     fn fn_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
     where
         I: Iterator<Item = ScanResult>,
     {
-        advance(it)?; // Skip Fn
+        self.fn_value(it)?;
+        Ok(())
+    }
+
+    /// Compile a function and push it on the stack, returning its name if it has one.
+    fn fn_value<I>(&mut self, it: &mut Peekable<I>) -> Result<Option<String>>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        let start = advance(it)?;
         let name = if let Some(Identifier(name)) = peek(it)? {
             let name = name.to_owned();
             advance(it)?;
@@ -515,18 +1410,22 @@ impl Compiler {
         };
 
         let mut fn_compiler = Compiler::new();
-        let function = fn_compiler.function(name.clone(), it)?;
+        let function = fn_compiler.function(name.clone(), start.loc, it)?;
+        self.absorb(&mut fn_compiler);
         self.emit(Instruction::Push(function));
-        if let Some(name) = name {
-            self.emit(Instruction::SetGlobal(name));
-        }
-        Ok(())
+        Ok(name)
     }
 
-    fn function<I>(&mut self, name: Option<String>, it: &mut Peekable<I>) -> Result<Value>
+    fn function<I>(
+        &mut self,
+        name: Option<String>,
+        loc: SourceLocation,
+        it: &mut Peekable<I>,
+    ) -> Result<Value>
     where
         I: Iterator<Item = ScanResult>,
     {
+        self.in_function = true;
         let arity = self.params(it)?;
 
         match peek(it)? {
@@ -540,19 +1439,88 @@ impl Compiler {
             Some(_) => {
                 let expected = vec![Arrow, LeftBracket];
                 let found = advance(it)?;
-                return Err(Error::Mismatch { expected, found });
+                return Err(Error::Mismatch {
+                    expected,
+                    found: found.into(),
+                });
             }
             None => {
                 return Err(Error::EndOfInput);
             }
         };
+        Ok(self.finish_function(name, arity, Some(loc)))
+    }
+
+    fn finish_function(
+        &mut self,
+        name: Option<String>,
+        arity: usize,
+        loc: Option<SourceLocation>,
+    ) -> Value {
+        debug_assert_eq!(
+            self.depth,
+            self.locals.len() + 1,
+            "Function body left the wrong number of values on the stack"
+        );
         self.close_scope(self.locals.len());
         self.emit(Instruction::Ret);
-        Ok(Value::Function {
-            chunk: Rc::new(self.instructions()),
-            arity,
+        let chunk = Chunk {
+            code: self.instructions(),
             name,
-        })
+            arity,
+            frame_size: self.max_locals,
+            loc,
+        };
+        Value::Function {
+            chunk: Rc::new(chunk),
+        }
+    }
+
+    /// Compile a whole script into a function taking no arguments,
+    /// which runs the script and returns the value of its last declaration.
+    pub fn module<I>(&mut self, name: String, it: &mut Peekable<I>) -> Result<Value>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        self.program(it)?;
+        if self.instrs.is_empty() {
+            self.emit(Instruction::Push(Value::Null));
+        }
+        Ok(self.finish_function(Some(name), 0, None))
+    }
+
+    fn bench_expr<I>(&mut self, it: &mut Peekable<I>) -> Result<()>
+    where
+        I: Iterator<Item = ScanResult>,
+    {
+        advance(it)?; // Skip Bench
+        let found = advance(it)?;
+        let name = if let Literal(Value::Str(name)) = found.ttype {
+            name.to_string()
+        } else {
+            let expected = vec![Literal(Value::Null)];
+            return Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            });
+        };
+        if let Some(LeftBracket) = peek(it)? {
+            let mut bench_compiler = Compiler::new();
+            bench_compiler.block(it)?;
+            let function = bench_compiler.finish_function(Some(name.clone()), 0, None);
+            self.benches.push((name, function));
+            self.absorb(&mut bench_compiler);
+            // Benchmarks only run through `oxide bench`, so the block itself evaluates to null.
+            self.emit(Instruction::Push(Value::Null));
+            Ok(())
+        } else {
+            let expected = vec![LeftBracket];
+            let found = advance(it)?;
+            Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            })
+        }
     }
 
     fn params<I>(&mut self, it: &mut Peekable<I>) -> Result<usize>
@@ -566,17 +1534,23 @@ impl Compiler {
             match found.ttype {
                 RightParen => Ok(arity),
                 Identifier(a) => {
+                    // The arguments are already on the stack, after the function itself.
+                    self.depth += 1;
                     self.declare_local(a, found.loc)?;
                     arity = 1;
                     while let Some(Comma) = peek(it)? {
                         advance(it)?;
                         let found = advance(it)?;
                         if let Identifier(a) = found.ttype {
+                            self.depth += 1;
                             self.declare_local(a, found.loc)?;
                             arity += 1;
                         } else {
                             let expected = vec![Identifier(String::new())];
-                            return Err(Error::Mismatch { expected, found });
+                            return Err(Error::Mismatch {
+                                expected,
+                                found: found.into(),
+                            });
                         }
                     }
                     let found = advance(it)?;
@@ -584,17 +1558,26 @@ impl Compiler {
                         Ok(arity)
                     } else {
                         let expected = vec![RightParen, Comma];
-                        Err(Error::Mismatch { expected, found })
+                        Err(Error::Mismatch {
+                            expected,
+                            found: found.into(),
+                        })
                     }
                 }
                 _ => {
                     let expected = vec![Identifier(String::new()), RightParen];
-                    Err(Error::Mismatch { expected, found })
+                    Err(Error::Mismatch {
+                        expected,
+                        found: found.into(),
+                    })
                 }
             }
         } else {
             let expected = vec![LeftParen];
-            Err(Error::Mismatch { expected, found })
+            Err(Error::Mismatch {
+                expected,
+                found: found.into(),
+            })
         }
     }
 
@@ -622,7 +1605,10 @@ impl Compiler {
                     Ok(argc)
                 } else {
                     let expected = vec![RightParen, Comma];
-                    Err(Error::Mismatch { expected, found })
+                    Err(Error::Mismatch {
+                        expected,
+                        found: found.into(),
+                    })
                 }
             }
         }
@@ -656,10 +1642,37 @@ pub enum Error {
     },
     Mismatch {
         expected: Vec<TokenType>,
-        found: Token,
+        found: Box<Token>,
+    },
+    BreakOutsideLoop(SourceLocation),
+    ContinueOutsideLoop(SourceLocation),
+    ReturnOutsideFunction(SourceLocation),
+    MisplacedImport(SourceLocation),
+    UnknownLabel {
+        label: String,
+        loc: SourceLocation,
     },
 }
 
+impl Error {
+    /// A secondary location related to the error, along with a label describing it.
+    pub fn note(&self) -> Option<(&'static str, SourceLocation)> {
+        match self {
+            Error::Scan(err) => err.note(),
+            _ => None,
+        }
+    }
+
+    /// Whether the source ended too early, so that more of it could make it compile.
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            Error::EndOfInput => true,
+            Error::Scan(err) => err.is_unterminated(),
+            _ => false,
+        }
+    }
+}
+
 impl TryLocate for Error {
     fn maybe_location(&self) -> Option<SourceLocation> {
         match self {
@@ -667,6 +1680,11 @@ impl TryLocate for Error {
             Error::Scan(err) => Some(err.location()),
             Error::Conversion { loc, .. } => Some(*loc),
             Error::Mismatch { found, .. } => Some(found.loc),
+            Error::BreakOutsideLoop(loc) => Some(*loc),
+            Error::ContinueOutsideLoop(loc) => Some(*loc),
+            Error::ReturnOutsideFunction(loc) => Some(*loc),
+            Error::MisplacedImport(loc) => Some(*loc),
+            Error::UnknownLabel { loc, .. } => Some(*loc),
         }
     }
 }
@@ -685,8 +1703,17 @@ impl Display for Error {
             Error::Conversion { .. } => write!(f, "Number too big to fit into VM code"),
             Error::Mismatch { expected, found } => {
                 write!(f, "Mismatched token: expected ")?;
-                human_readable_fmt(&expected, f)?;
-                write!(f, ", found '{}'", found.ttype)
+                human_readable_fmt(expected, f)?;
+                write!(f, ", found '{}'", found.lexeme)
+            }
+            Error::BreakOutsideLoop(_) => write!(f, "'break' outside of a loop"),
+            Error::ContinueOutsideLoop(_) => write!(f, "'continue' outside of a loop"),
+            Error::ReturnOutsideFunction(_) => write!(f, "'return' outside of a function"),
+            Error::MisplacedImport(_) => {
+                write!(f, "'import * from' can only be used as a statement")
+            }
+            Error::UnknownLabel { label, .. } => {
+                write!(f, "No enclosing loop labelled '{}'", label)
             }
         }
     }
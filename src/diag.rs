@@ -0,0 +1,235 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::loc::SourceLocation;
+use crate::vm::{json, Key, Value};
+
+/// Every warning the compiler knows about, along with whether it is reported by default.
+pub const LINTS: &[(&str, Level)] = &[
+    ("unused-variable", Level::Warn),
+    ("implicit-global", Level::Warn),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// How diagnostics are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Messages with the lines of code they refer to, for people to read.
+    #[default]
+    Human,
+    /// A JSON object per line, for editors and other tools to parse.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown error format '{}', expected human or json",
+                s
+            )),
+        }
+    }
+}
+
+thread_local! {
+    static FORMAT: Cell<Format> = const { Cell::new(Format::Human) };
+}
+
+/// Print every diagnostic from now on in the given format.
+pub fn set_format(format: Format) {
+    FORMAT.with(|f| f.set(format));
+}
+
+/// Something worth telling the user about a script, with the location it refers to.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub loc: Option<SourceLocation>,
+    /// The name of the lint which produced the diagnostic, used to silence it.
+    /// Errors which can't be silenced have an empty code.
+    pub code: &'static str,
+    /// A secondary location, along with a label describing it.
+    pub note: Option<(&'static str, SourceLocation)>,
+    /// The file the diagnostic refers to, if it isn't the script being run.
+    pub file: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn warning(code: &'static str, message: String, loc: SourceLocation) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+            loc: Some(loc),
+            code,
+            note: None,
+            file: None,
+        }
+    }
+
+    /// Print the diagnostic, along with the lines of `source` it refers to.
+    /// Without the source, only the position of those lines is shown.
+    pub fn emit(&self, source: Option<&str>) {
+        if FORMAT.with(Cell::get) == Format::Json {
+            return eprintln!("{}", self.to_json());
+        }
+        if let Some(file) = &self.file {
+            eprintln!("In {}:", file);
+        }
+        if self.code.is_empty() {
+            eprintln!("{}", self.message);
+        } else {
+            eprintln!("{}[{}]: {}", self.severity, self.code, self.message);
+        }
+        let notes = self.loc.map(|loc| ("", loc)).into_iter().chain(self.note);
+        for (label, loc) in notes {
+            eprintln!("  --> {}", loc);
+            if let Some(source) = source {
+                print_snippet(loc, source, label);
+            }
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let severity = Value::new_str(self.severity.to_string());
+        let code = Some(self.code)
+            .filter(|code| !code.is_empty())
+            .map_or(Value::Null, Value::new_str);
+        let note = self.note.map_or(Value::Null, |(label, loc)| {
+            object(vec![("label", Value::new_str(label)), ("span", span(loc))])
+        });
+        let diag = object(vec![
+            ("severity", severity),
+            ("code", code),
+            ("message", Value::new_str(self.message.as_str())),
+            (
+                "file",
+                self.file.clone().map_or(Value::Null, Value::new_str),
+            ),
+            ("span", self.loc.map_or(Value::Null, span)),
+            ("note", note),
+        ]);
+        json::encode(&diag).expect("diagnostics only hold values JSON can represent")
+    }
+}
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    let entries: IndexMap<_, _> = fields
+        .into_iter()
+        .map(|(name, val)| {
+            let key = Key::new(Value::new_str(name)).expect("strings are valid keys");
+            (key, val)
+        })
+        .collect();
+    Value::new_map_from(entries)
+}
+
+fn span(loc: SourceLocation) -> Value {
+    let num = |n: usize| Value::Num(n as f64);
+    object(vec![
+        ("line", num(loc.line)),
+        ("column", num(loc.column)),
+        ("offset", num(loc.offset)),
+        ("length", num(loc.len)),
+    ])
+}
+
+/// Print the line of `source` containing `loc`, and underline the location itself.
+fn print_snippet(loc: SourceLocation, source: &str, label: &str) {
+    let (line, loc) = loc.context(source);
+    let (before, text, _) = loc.split_source(line);
+    let text = text.lines().next().unwrap_or("");
+    eprintln!("    {}", line.lines().next().unwrap_or(""));
+    // Tabs are kept, so that the underline lines up with the text however wide they are.
+    let indent: String = before
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let underline = format!(
+        "{}{} {}",
+        indent,
+        "^".repeat(text.chars().count().max(1)),
+        label
+    );
+    eprintln!("    {}", underline.trim_end());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+}
+
+/// Which warnings get reported, as chosen on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    levels: HashMap<&'static str, Level>,
+    /// Treat every reported warning as an error, which stops the script from running.
+    pub deny_warnings: bool,
+}
+
+impl Policy {
+    /// Report the lint `code` at `level`, failing if there's no such lint.
+    pub fn set(&mut self, code: &str, level: Level) -> Result<(), String> {
+        let (code, _) = LINTS
+            .iter()
+            .find(|(name, _)| *name == code)
+            .ok_or_else(|| format!("Unknown warning '{}'", code))?;
+        self.levels.insert(code, level);
+        Ok(())
+    }
+
+    fn level(&self, code: &str) -> Level {
+        self.levels.get(code).copied().unwrap_or_else(|| {
+            LINTS
+                .iter()
+                .find(|(name, _)| *name == code)
+                .map_or(Level::Warn, |(_, level)| *level)
+        })
+    }
+
+    /// Print the warnings in `diagnostics` which aren't allowed,
+    /// returning how many of them were turned into errors.
+    pub fn report(&self, diagnostics: &[Diagnostic], source: Option<&str>) -> usize {
+        let mut denied = 0;
+        for diag in diagnostics {
+            if self.level(diag.code) == Level::Allow {
+                continue;
+            }
+            if self.deny_warnings {
+                let diag = Diagnostic {
+                    severity: Severity::Error,
+                    ..diag.clone()
+                };
+                diag.emit(source);
+                denied += 1;
+            } else {
+                diag.emit(source);
+            }
+        }
+        denied
+    }
+}
@@ -1,67 +1,395 @@
+pub mod bench;
+mod flags;
+mod inspect;
 mod libs;
+mod logging;
+mod modules;
+mod output;
+mod pattern;
+mod readline;
+mod repl;
+pub mod session;
+mod signals;
+mod store;
+mod temp;
+mod term;
+mod trace;
+mod transcript;
 
+use std::env;
 use std::fmt::{self, Display};
 use std::fs::File;
 use std::io::{self, Read as _};
-use std::path::Path;
-use std::rc::Rc;
+use std::path::{Path, PathBuf};
 
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use indexmap::IndexMap;
 
+use crate::bundle::Bundle;
 use crate::compile::{self, Compiler};
+use crate::diag::{Diagnostic, Format, Policy, Severity};
 use crate::loc::{SourceLocation, TryLocate};
+use crate::project::Workspace;
 use crate::scan::TokenStream;
-use crate::vm::{self, Value, VirtualMachine};
+use crate::vm::{self, Chunk, Key, Limits, Value, VirtualMachine};
+use crate::xref::CallGraph;
+use modules::Importer;
 
-pub fn run_file<P: AsRef<Path>>(path: P) -> Result<()> {
+pub use repl::repl;
+pub use transcript::check_transcript;
+
+/// The environment variable listing directories to search for modules.
+const PATH_VAR: &str = "OXIDE_PATH";
+
+/// Settings for running scripts, as chosen on the command line.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub policy: Policy,
+    /// Directories to search for modules, given with `--path`.
+    pub paths: Vec<PathBuf>,
+    pub limits: Limits,
+    /// How errors and warnings are printed, given with `--error-format`.
+    pub error_format: Format,
+    /// Print the value of a script's last declaration once it finishes, given with `--print-last`.
+    pub print_last: bool,
+    /// Truncate fractional indices instead of failing, given with `--allow-float-index`.
+    pub allow_float_index: bool,
+    /// The arguments following the script on the command line, which it sees as `args`.
+    pub args: Vec<String>,
+    /// Print every function call and what it returns, given with `--trace-calls`.
+    pub trace_calls: bool,
+    /// Make runs repeatable by not depending on the clock, given with `--deterministic`.
+    pub deterministic: bool,
+}
+
+impl Config {
+    /// Create a VM with the standard library loaded, which imports modules from the directory
+    /// of the script being run first, then from `extra_paths` and the configured directories.
+    fn vm(&self, chunk: Chunk, script_dir: &Path, extra_paths: Vec<PathBuf>) -> VirtualMachine {
+        self.vm_importing(chunk, self.importer(script_dir, extra_paths))
+    }
+
+    fn vm_importing(&self, chunk: Chunk, importer: Importer) -> VirtualMachine {
+        let mut vm = VirtualMachine::new(chunk);
+        vm.set_limits(self.limits);
+        vm.allow_float_index(self.allow_float_index);
+        libs::load_libraries(&mut vm, self);
+        importer.define(&mut vm);
+        vm.protect_globals();
+        if self.trace_calls {
+            vm.set_hooks(trace::call_tracer());
+        }
+        if self.deterministic {
+            logging::use_logical_clock();
+        }
+        let args = self.args.iter().map(|arg| Value::new_str(arg.as_str()));
+        vm.define("args".to_owned(), Value::new_array(args.collect()));
+        vm
+    }
+
+    fn importer(&self, script_dir: &Path, extra_paths: Vec<PathBuf>) -> Importer {
+        let mut paths = vec![script_dir.to_owned()];
+        paths.extend(self.paths.iter().cloned());
+        paths.extend(extra_paths);
+        if let Some(var) = env::var_os(PATH_VAR) {
+            paths.extend(env::split_paths(&var));
+        }
+        Importer::new(paths, self.policy.clone())
+    }
+
+    /// Print the value a script left behind if asked to, unless there's nothing to show.
+    fn finish(&self, vm: &mut VirtualMachine) {
+        if !self.print_last {
+            return;
+        }
+        match vm.pop() {
+            Ok(Value::Null) | Err(_) => (),
+            Ok(val) => println!("{}", val),
+        }
+    }
+}
+
+/// The directory containing the script at `path`.
+fn script_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Run the script at `path`, returning whether it finished without an error.
+pub fn run_file<P: AsRef<Path>>(path: P, config: &Config) -> bool {
+    let path = path.as_ref();
+    match read_source(path) {
+        Ok(text) => {
+            let mut compiler = Compiler::new();
+            let mut vm = config.vm(Chunk::default(), script_dir(path), Vec::new());
+            match run_program(&text, &mut compiler, &mut vm, config) {
+                Ok(()) => true,
+                Err(err) => {
+                    print_error(&err, &text);
+                    false
+                }
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
+    }
+}
+
+/// Run the entry point of a project, with its metadata available
+/// to the script through the global `project`.
+pub fn run_project(workspace: &Workspace, config: &Config) -> bool {
+    let path = workspace.entry();
+    let text = match read_source(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            return false;
+        }
+    };
+    let mut compiler = Compiler::new();
+    let mut vm = config.vm(Chunk::default(), script_dir(&path), workspace.paths());
+    vm.define("project".to_owned(), project_info(workspace));
+    match run_program(&text, &mut compiler, &mut vm, config) {
+        Ok(()) => true,
+        Err(err) => {
+            print_error(&err, &text);
+            false
+        }
+    }
+}
+
+fn project_info(workspace: &Workspace) -> Value {
+    let project = &workspace.manifest.project;
+    let path_str = |path: &Path| Value::new_str(path.to_string_lossy());
+    let paths = workspace.paths().iter().map(|p| path_str(p)).collect();
+    let mut info = IndexMap::new();
+    let mut insert = |key: &str, val: Option<Value>| {
+        if let Some(val) = val {
+            let key = Key::new(Value::new_str(key)).expect("strings are valid keys");
+            info.insert(key, val);
+        }
+    };
+    insert("name", project.name.clone().map(Value::new_str));
+    insert("version", project.version.clone().map(Value::new_str));
+    insert(
+        "description",
+        project.description.clone().map(Value::new_str),
+    );
+    insert("root", Some(path_str(&workspace.root)));
+    insert("paths", Some(Value::new_array(paths)));
+    Value::new_map_from(info)
+}
+
+/// Pack the script at `path` and the modules it imports into a copy of the interpreter
+/// at `output`, which runs the script when started.
+pub fn bundle_file<P: AsRef<Path>>(path: P, output: &Path, config: &Config) -> bool {
+    let path = path.as_ref();
+    let source = match read_source(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        }
+    };
+    let mut compiler = Compiler::new();
+    let mut stream = TokenStream::new(&source).peekable();
+    let script = compiler
+        .module("<script>".to_owned(), &mut stream)
+        .map_err(Error::from)
+        .and_then(|script| check(&mut compiler, &config.policy, Some(&source)).map(|()| script));
+    let script = match script {
+        Ok(script) => script,
+        Err(err) => {
+            print_error(&err, &source);
+            return false;
+        }
+    };
+    let modules = match config
+        .importer(script_dir(path), Vec::new())
+        .collect(&script)
+    {
+        Ok(modules) => modules,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        }
+    };
+    let bundle = Bundle {
+        name: path.display().to_string(),
+        source,
+        modules,
+    };
+    match bundle.write(output) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("{}: {}", output.display(), err);
+            false
+        }
+    }
+}
+
+/// Run the script bundled into the running executable.
+pub fn run_bundle(bundle: Bundle, config: &Config) -> bool {
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.to_owned()))
+        .unwrap_or_else(|| PathBuf::from("."));
+    let importer = config
+        .importer(&exe_dir, Vec::new())
+        .with_bundled(bundle.modules);
+    let mut compiler = Compiler::new();
+    let mut vm = config.vm_importing(Chunk::default(), importer);
+    match run_program(&bundle.source, &mut compiler, &mut vm, config) {
+        Ok(()) => true,
+        Err(err) => {
+            print_error_in(&err, &bundle.source, &bundle.name);
+            false
+        }
+    }
+}
+
+/// Run a script read from standard input, compiling it as it arrives
+/// rather than waiting for all of it.
+pub fn run_stdin(config: &Config) -> bool {
+    let stdin = io::stdin();
+    let mut stream = TokenStream::from_reader(stdin.lock()).peekable();
+    let mut compiler = Compiler::new();
+    // The source is gone by now, so only the location of diagnostics can be shown.
+    let result = compiler
+        .program(&mut stream)
+        .map_err(Error::from)
+        .and_then(|()| check(&mut compiler, &config.policy, None))
+        .and_then(|()| {
+            let chunk = Chunk::script(compiler.instructions());
+            let mut vm = config.vm(chunk, Path::new("."), Vec::new());
+            vm.run()?;
+            config.finish(&mut vm);
+            Ok(())
+        });
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            err.diagnostic().emit(None);
+            false
+        }
+    }
+}
+
+/// Run every `bench` block of the script at `path`, after evaluating the script itself
+/// so that the functions and globals it declares are available to the benchmarks.
+pub fn bench_file<P: AsRef<Path>>(path: P, options: &bench::Options, config: &Config) -> bool {
+    let path = path.as_ref();
+    let text = match read_source(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        }
+    };
+    let mut compiler = Compiler::new();
+    let mut vm = config.vm(Chunk::default(), script_dir(path), Vec::new());
+    if let Err(err) = run_program(&text, &mut compiler, &mut vm, config) {
+        print_error(&err, &text);
+        return false;
+    }
+    match bench::run(&mut vm, compiler.benches(), options) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
+    }
+}
+
+/// Print which globals and functions each function of the script at `path` refers to.
+pub fn dump_calls<P: AsRef<Path>>(path: P) {
+    let text = match read_source(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    let mut compiler = Compiler::new();
+    let mut stream = TokenStream::new(&text).peekable();
+    if let Err(err) = compiler.program(&mut stream) {
+        print_error(&err.into(), &text);
+        return;
+    }
+    let chunk = compiler.instructions();
+    println!("{}", CallGraph::new(&chunk, &compiler.benches()));
+}
+
+/// Print every token of the script at `path`, along with its exact text
+/// and the whitespace and comments which precede it.
+pub fn dump_tokens<P: AsRef<Path>>(path: P) {
+    let text = match read_source(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    for token in TokenStream::new(&text) {
+        match token {
+            Ok(token) => println!(
+                "{:<8}{:<12}{:<16}{:?}",
+                token.loc.to_string(),
+                token.ttype.to_string(),
+                format!("{:?}", token.lexeme),
+                token.trivia
+            ),
+            Err(err) => return print_error(&compile::Error::from(err).into(), &text),
+        }
+    }
+}
+
+fn read_source<P: AsRef<Path>>(path: P) -> Result<String> {
     let mut text = String::new();
     let mut file = File::open(path.as_ref())?;
     file.read_to_string(&mut text)?;
-    let mut compiler = Compiler::new();
-    let mut stream = TokenStream::new(&text).peekable();
+    Ok(text)
+}
+
+fn run_program(
+    text: &str,
+    compiler: &mut Compiler,
+    vm: &mut VirtualMachine,
+    config: &Config,
+) -> Result<()> {
+    let mut stream = TokenStream::new(text).peekable();
     compiler.program(&mut stream)?;
+    check(compiler, &config.policy, Some(text))?;
     let chunk = compiler.instructions();
-    let mut vm = VirtualMachine::new(Rc::new(chunk));
-    libs::load_libraries(&mut vm);
+    vm.change_chunk(Chunk::script(chunk));
     vm.run()?;
+    config.finish(vm);
     Ok(())
 }
 
-pub fn repl() {
-    let mut rl = Editor::<()>::new();
-    let mut compiler = Compiler::new();
-    let mut vm = VirtualMachine::new(Rc::new(Vec::new()));
-    libs::load_libraries(&mut vm);
-    loop {
-        let readline = rl.readline(">> ");
-        match readline {
-            Ok(line) => {
-                let line = line.as_str();
-                rl.add_history_entry(line);
-                match run_line(line, &mut compiler, &mut vm) {
-                    Ok(val) => println!("{}", val),
-                    Err(err) => eprintln!("{}", err),
-                }
-            }
-            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
-                break;
-            }
-            Err(err) => {
-                eprintln!("Error: {:?}", err);
-                break;
-            }
-        }
+/// Report the warnings found while compiling, failing if any of them were denied.
+fn check(compiler: &mut Compiler, policy: &Policy, source: Option<&str>) -> Result<()> {
+    match policy.report(&compiler.diagnostics(), source) {
+        0 => Ok(()),
+        denied => Err(Error::Denied(denied)),
     }
 }
 
-fn run_line(text: &str, compiler: &mut Compiler, vm: &mut VirtualMachine) -> Result<Value> {
-    let mut stream = TokenStream::new(text).peekable();
-    compiler.declaration(&mut stream)?;
-    let chunk = Rc::new(compiler.instructions());
-    vm.change_chunk(chunk);
-    vm.run()?;
-    Ok(vm.pop()?)
+/// Print `err` along with the lines of `source` it refers to, if any.
+fn print_error(err: &Error, source: &str) {
+    err.diagnostic().emit(Some(source));
+}
+
+/// Print `err` like `print_error`, pointing out that it's in `file` rather than the script being run.
+fn print_error_in(err: &Error, source: &str, file: &str) {
+    let diag = Diagnostic {
+        file: Some(file.to_owned()),
+        ..err.diagnostic()
+    };
+    diag.emit(Some(source));
 }
 
 #[derive(Debug)]
@@ -69,6 +397,8 @@ pub enum Error {
     IO(io::Error),
     Compilation(compile::Error),
     Runtime(vm::Error),
+    /// Compilation succeeded, but produced warnings which were treated as errors.
+    Denied(usize),
 }
 
 impl TryLocate for Error {
@@ -76,7 +406,24 @@ impl TryLocate for Error {
         match self {
             Error::IO(_) => None,
             Error::Compilation(err) => err.maybe_location(),
-            Error::Runtime(_) => None,
+            Error::Runtime(_) | Error::Denied(_) => None,
+        }
+    }
+}
+
+impl Error {
+    fn diagnostic(&self) -> Diagnostic {
+        let note = match self {
+            Error::Compilation(err) => err.note(),
+            _ => None,
+        };
+        Diagnostic {
+            severity: Severity::Error,
+            message: self.to_string(),
+            loc: self.maybe_location(),
+            code: "",
+            note,
+            file: None,
         }
     }
 }
@@ -87,6 +434,10 @@ impl Display for Error {
             Error::IO(err) => write!(f, "{}", err),
             Error::Compilation(err) => write!(f, "Compilation error: {}", err),
             Error::Runtime(err) => write!(f, "Runtime error: {}", err),
+            Error::Denied(1) => write!(f, "Not running the script because of a denied warning"),
+            Error::Denied(n) => {
+                write!(f, "Not running the script because of {} denied warnings", n)
+            }
         }
     }
 }
@@ -97,6 +448,7 @@ impl std::error::Error for Error {
             Error::IO(err) => Some(err),
             Error::Compilation(err) => Some(err),
             Error::Runtime(err) => Some(err),
+            Error::Denied(_) => None,
         }
     }
 }
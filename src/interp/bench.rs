@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::vm::{Value, VirtualMachine};
+
+use super::Result;
+
+const WARMUP_RUNS: usize = 5;
+const SAMPLE_RUNS: usize = 30;
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// File with the results of a previous run to compare against.
+    pub baseline: Option<PathBuf>,
+    /// File to save the results of this run to.
+    pub save: Option<PathBuf>,
+}
+
+struct Stats {
+    mean: f64,
+    median: f64,
+    stddev: f64,
+}
+
+impl Stats {
+    fn new(samples: &mut [f64]) -> Self {
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let mid = samples.len() / 2;
+        let median = if samples.len() % 2 == 1 {
+            samples[mid]
+        } else {
+            (samples[mid - 1] + samples[mid]) / 2.0
+        };
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+        Stats {
+            mean,
+            median,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+/// Format a duration given in seconds with a unit that keeps it readable.
+fn human_duration(secs: f64) -> String {
+    if secs < 1e-6 {
+        format!("{:.1} ns", secs * 1e9)
+    } else if secs < 1e-3 {
+        format!("{:.2} µs", secs * 1e6)
+    } else if secs < 1.0 {
+        format!("{:.2} ms", secs * 1e3)
+    } else {
+        format!("{:.2} s", secs)
+    }
+}
+
+fn measure(vm: &mut VirtualMachine, f: &Value) -> Result<Stats> {
+    for _ in 0..WARMUP_RUNS {
+        vm.call(f.clone(), Vec::new())?;
+    }
+    let mut samples = Vec::with_capacity(SAMPLE_RUNS);
+    for _ in 0..SAMPLE_RUNS {
+        let start = Instant::now();
+        vm.call(f.clone(), Vec::new())?;
+        samples.push(start.elapsed().as_secs_f64());
+    }
+    Ok(Stats::new(&mut samples))
+}
+
+/// Read a baseline file, made of lines of benchmark names and mean times in seconds.
+fn load_baseline(path: &Path) -> io::Result<HashMap<String, f64>> {
+    let text = fs::read_to_string(path)?;
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (name, mean) = line.rsplit_once('\t').ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Malformed baseline entry")
+            })?;
+            let mean = mean
+                .parse::<f64>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok((name.to_owned(), mean))
+        })
+        .collect()
+}
+
+fn save_baseline(path: &Path, results: &[(String, Stats)]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for (name, stats) in results {
+        writeln!(file, "{}\t{}", name, stats.mean)?;
+    }
+    file.flush()
+}
+
+/// Run each benchmark, print its statistics, and compare or save them as requested.
+pub fn run(
+    vm: &mut VirtualMachine,
+    benches: Vec<(String, Value)>,
+    options: &Options,
+) -> Result<()> {
+    let baseline = match &options.baseline {
+        Some(path) => load_baseline(path)?,
+        None => HashMap::new(),
+    };
+    let width = benches
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut results = Vec::new();
+    for (name, f) in benches {
+        let stats = match measure(vm, &f) {
+            Ok(stats) => stats,
+            Err(err) => {
+                eprintln!("bench {:width$}  failed: {}", name, err, width = width);
+                continue;
+            }
+        };
+        print!(
+            "bench {:width$}  mean {:>10}  median {:>10}  stddev {:>10}",
+            name,
+            human_duration(stats.mean),
+            human_duration(stats.median),
+            human_duration(stats.stddev),
+            width = width
+        );
+        if let Some(old) = baseline.get(&name) {
+            let change = (stats.mean - old) / old * 100.0;
+            print!("  ({:+.1}% vs baseline)", change);
+        }
+        println!();
+        results.push((name, stats));
+    }
+    if let Some(path) = &options.save {
+        save_baseline(path, &results)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::vm::{Key, Result, Value, ValueError};
+
+/// The kind of value an option takes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A switch which is true if it's given, and takes no value.
+    Bool,
+    Str,
+    Num,
+}
+
+impl FromStr for Kind {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, ValueError> {
+        match s {
+            "bool" => Ok(Kind::Bool),
+            "str" => Ok(Kind::Str),
+            "num" => Ok(Kind::Num),
+            _ => Err(ValueError::InvalidArgument(format!(
+                "Unknown option kind '{}', expected bool, str or num",
+                s
+            ))),
+        }
+    }
+}
+
+struct Opt {
+    name: String,
+    kind: Kind,
+    help: String,
+}
+
+impl Opt {
+    /// The key the option's value is stored under, which is its name without the leading dashes.
+    fn key(&self) -> Key {
+        let key = Value::new_str(self.name.trim_start_matches('-'));
+        Key::new(key).expect("strings are valid keys")
+    }
+}
+
+/// The options a script accepts on the command line, which parse its arguments into a map.
+pub struct Flags {
+    description: String,
+    options: RefCell<Vec<Opt>>,
+}
+
+impl Flags {
+    pub fn new(description: String) -> Self {
+        Flags {
+            description,
+            options: RefCell::default(),
+        }
+    }
+
+    pub fn add(&self, name: &str, kind: Kind, help: String) -> Result<()> {
+        if !name.starts_with('-') || name.len() < 2 {
+            let msg = format!("Option '{}' must start with a dash", name);
+            return Err(ValueError::InvalidArgument(msg).into());
+        }
+        let mut options = self.options.borrow_mut();
+        options.retain(|opt| opt.name != name);
+        options.push(Opt {
+            name: name.to_owned(),
+            kind,
+            help,
+        });
+        Ok(())
+    }
+
+    /// Parse `args` into a map from option names, without their dashes, to their values.
+    /// Options which weren't given are false if they're switches, and null otherwise.
+    /// The other arguments are stored in order under `rest`.
+    /// Returns None if help was asked for with `--help` or `-h`.
+    pub fn parse(&self, args: &[String]) -> Result<Option<IndexMap<Key, Value>>> {
+        let options = self.options.borrow();
+        let mut parsed: IndexMap<_, _> = options
+            .iter()
+            .map(|opt| match opt.kind {
+                Kind::Bool => (opt.key(), Value::Bool(false)),
+                _ => (opt.key(), Value::Null),
+            })
+            .collect();
+        let mut rest = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                rest.extend(args.by_ref().map(Value::new_str));
+                break;
+            }
+            if arg == "--help" || arg == "-h" {
+                return Ok(None);
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                rest.push(Value::new_str(arg.as_str()));
+                continue;
+            }
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            let opt = options
+                .iter()
+                .find(|opt| opt.name == name)
+                .ok_or_else(|| invalid(format!("Unknown option '{}'", name)))?;
+            let val = match (opt.kind, inline) {
+                (Kind::Bool, None) => Value::Bool(true),
+                (Kind::Bool, Some(_)) => {
+                    return Err(invalid(format!("Option '{}' doesn't take a value", name)));
+                }
+                (kind, inline) => {
+                    let text = inline
+                        .or_else(|| args.next().map(String::as_str))
+                        .ok_or_else(|| invalid(format!("Missing value for '{}'", name)))?;
+                    if kind == Kind::Num {
+                        let x = text.parse().map_err(|_| {
+                            invalid(format!(
+                                "Expected a number for '{}', found '{}'",
+                                name, text
+                            ))
+                        })?;
+                        Value::Num(x)
+                    } else {
+                        Value::new_str(text)
+                    }
+                }
+            };
+            parsed.insert(opt.key(), val);
+        }
+        let rest_key = Key::new(Value::new_str("rest")).expect("strings are valid keys");
+        parsed.insert(rest_key, Value::new_array(rest));
+        Ok(Some(parsed))
+    }
+
+    /// A description of every option, to show when asked for help.
+    pub fn help(&self) -> String {
+        let options = self.options.borrow();
+        let usages: Vec<_> = options
+            .iter()
+            .map(|opt| match opt.kind {
+                Kind::Bool => opt.name.clone(),
+                Kind::Str => format!("{} <str>", opt.name),
+                Kind::Num => format!("{} <num>", opt.name),
+            })
+            .chain(Some("-h, --help".to_owned()))
+            .collect();
+        let helps = options
+            .iter()
+            .map(|opt| opt.help.as_str())
+            .chain(Some("Show this help"));
+        let width = usages.iter().map(String::len).max().unwrap_or(0);
+        let mut text = String::new();
+        if !self.description.is_empty() {
+            text.push_str(&self.description);
+            text.push_str("\n\n");
+        }
+        text.push_str("Options:");
+        for (usage, help) in usages.iter().zip(helps) {
+            let line = format!("\n  {:width$}  {}", usage, help, width = width);
+            text.push_str(line.trim_end());
+        }
+        text
+    }
+}
+
+fn invalid(msg: String) -> crate::vm::Error {
+    ValueError::InvalidArgument(msg).into()
+}
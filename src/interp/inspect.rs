@@ -0,0 +1,135 @@
+use std::fmt::Write as _;
+use std::rc::Rc;
+
+use crate::vm::Value;
+
+/// Elements of a single array, map or set shown before the rest are left out.
+const MAX_ELEMENTS: usize = 100;
+/// Widest an aggregate without nested aggregates can be to fit on a single line.
+const MAX_INLINE_WIDTH: usize = 72;
+
+/// Render `val` across multiple lines, indenting nested arrays, maps and sets.
+/// Aggregates nested deeper than `depth` are abbreviated.
+pub fn render(val: &Value, depth: usize) -> String {
+    let mut inspector = Inspector {
+        out: String::new(),
+        seen: Vec::new(),
+    };
+    inspector.value(val, 0, depth);
+    inspector.out
+}
+
+struct Inspector {
+    out: String,
+    /// The aggregates currently being rendered, to spot the ones containing themselves.
+    seen: Vec<*const ()>,
+}
+
+/// The entries of an aggregate, as an optional key and a value.
+type Entries = Vec<(Option<Value>, Value)>;
+
+impl Inspector {
+    fn value(&mut self, val: &Value, indent: usize, depth: usize) {
+        let (ptr, open, close, entries): (*const (), _, _, Entries) = match val {
+            Value::Array(a) => {
+                let entries = a.borrow().iter().map(|v| (None, v.clone())).collect();
+                (Rc::as_ptr(a) as _, "[", "]", entries)
+            }
+            Value::Map(m) => {
+                let entries = m
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (Some(k.value().clone()), v.clone()))
+                    .collect();
+                (Rc::as_ptr(m) as _, "{", "}", entries)
+            }
+            Value::Set(s) => {
+                let entries = s
+                    .borrow()
+                    .iter()
+                    .map(|k| (None, k.value().clone()))
+                    .collect();
+                (Rc::as_ptr(s) as _, "{", "}", entries)
+            }
+            Value::Ok(val) => return self.wrapped("ok", val, indent, depth),
+            Value::Err(err) => return self.wrapped("err", err, indent, depth),
+            Value::Str(s) => return write!(self.out, "{:?}", s).unwrap(),
+            val => return write!(self.out, "{}", val).unwrap(),
+        };
+        if self.seen.contains(&ptr) {
+            self.out.push_str("<cycle>");
+        } else if entries.is_empty() {
+            write!(self.out, "{}{}", open, close).unwrap();
+        } else if depth == 0 {
+            write!(self.out, "{}...{}", open, close).unwrap();
+        } else {
+            self.seen.push(ptr);
+            if !self.inline(&entries, open, close) {
+                self.multiline(&entries, open, close, indent, depth);
+            }
+            self.seen.pop();
+        }
+    }
+
+    fn wrapped(&mut self, name: &str, val: &Value, indent: usize, depth: usize) {
+        write!(self.out, "{}(", name).unwrap();
+        self.value(val, indent, depth);
+        self.out.push(')');
+    }
+
+    /// Render short aggregates of simple values on one line, returning whether that was possible.
+    fn inline(&mut self, entries: &Entries, open: &str, close: &str) -> bool {
+        let simple = |v: &Value| {
+            !matches!(
+                v,
+                Value::Array(_) | Value::Map(_) | Value::Set(_) | Value::Ok(_) | Value::Err(_)
+            )
+        };
+        if entries.len() > MAX_ELEMENTS || !entries.iter().all(|(_, v)| simple(v)) {
+            return false;
+        }
+        let mut line = String::from(open);
+        for (i, (key, val)) in entries.iter().enumerate() {
+            if i > 0 {
+                line.push_str(", ");
+            }
+            if let Some(key) = key {
+                line.push_str(&render(key, 0));
+                line.push_str(": ");
+            }
+            line.push_str(&render(val, 0));
+        }
+        line.push_str(close);
+        if line.chars().count() > MAX_INLINE_WIDTH {
+            return false;
+        }
+        self.out.push_str(&line);
+        true
+    }
+
+    fn multiline(
+        &mut self,
+        entries: &Entries,
+        open: &str,
+        close: &str,
+        indent: usize,
+        depth: usize,
+    ) {
+        let pad = "  ".repeat(indent + 1);
+        self.out.push_str(open);
+        for (key, val) in entries.iter().take(MAX_ELEMENTS) {
+            write!(self.out, "\n{}", pad).unwrap();
+            if let Some(key) = key {
+                self.value(key, indent + 1, depth - 1);
+                self.out.push_str(": ");
+            }
+            self.value(val, indent + 1, depth - 1);
+            self.out.push(',');
+        }
+        if entries.len() > MAX_ELEMENTS {
+            let more = entries.len() - MAX_ELEMENTS;
+            write!(self.out, "\n{}... ({} more)", pad, more).unwrap();
+        }
+        write!(self.out, "\n{}{}", "  ".repeat(indent), close).unwrap();
+    }
+}
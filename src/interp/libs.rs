@@ -1,18 +1,1652 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto as _;
+use std::fs;
+use std::hash::{DefaultHasher, Hash as _, Hasher as _};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use crate::vm::{Value, ValueError, VirtualMachine};
+use indexmap::{IndexMap, IndexSet};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive as _, ToPrimitive as _};
+use rustyline::error::ReadlineError;
 
-fn print(vals: &[Value]) -> Result<Value, ValueError> {
-    println!("{}", vals[0]);
+use crate::interp::flags::{Flags, Kind};
+use crate::interp::inspect;
+use crate::interp::logging::{self, Level};
+use crate::interp::output;
+use crate::interp::pattern;
+use crate::interp::readline;
+use crate::interp::signals;
+use crate::interp::store::Store;
+use crate::interp::temp::TempPaths;
+use crate::interp::term;
+use crate::interp::Config;
+use crate::vm::decimal::{Decimal, Rounding};
+use crate::vm::json;
+use crate::vm::{Arity, Key, Result, Value, ValueError, VirtualMachine, WeakRef};
+
+fn wrong_type(expected: &'static str, found: &Value) -> crate::vm::Error {
+    ValueError::WrongType {
+        expected,
+        found: found.clone(),
+    }
+    .into()
+}
+
+/// Render values the way `print` shows them, separated by `sep`.
+fn joined(vals: &[Value], sep: &str) -> String {
+    let parts: Vec<_> = vals.iter().map(Value::to_string).collect();
+    parts.join(sep)
+}
+
+/// Print any number of values separated by spaces, followed by a newline.
+fn print(vals: &[Value]) -> Result<Value> {
+    output::write(&format!("{}\n", joined(vals, " ")))?;
+    Ok(Value::Null)
+}
+
+/// Print any number of values separated by spaces, without ending the line.
+fn write(vals: &[Value]) -> Result<Value> {
+    output::write(&joined(vals, " "))?;
+    Ok(Value::Null)
+}
+
+/// Join the elements of an array into a string, with a separator between each of them.
+fn join(vals: &[Value]) -> Result<Value> {
+    let sep = str_arg(&vals[1])?;
+    Ok(Value::new_str(joined(&elements(&vals[0])?, sep)))
+}
+
+/// How deeply `inspect` renders nested aggregates, unless told otherwise.
+const INSPECT_DEPTH: usize = 8;
+
+/// Render a value over multiple lines, for a readable view of nested data.
+fn inspect(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let depth = vals
+        .get(1)
+        .map(|val| vm.as_index(val))
+        .transpose()?
+        .unwrap_or(INSPECT_DEPTH);
+    Ok(Value::new_str(inspect::render(&vals[0], depth)))
+}
+
+fn len(vals: &[Value]) -> Result<Value> {
+    let len = match &vals[0] {
+        Value::Str(s) => s.chars().count(),
+        Value::Array(a) => a.borrow().len(),
+        Value::Map(m) => m.borrow().len(),
+        Value::Set(s) => s.borrow().len(),
+        val => return Err(wrong_type("Str, Array, Map or Set", val)),
+    };
+    Ok(Value::Int(len as i64))
+}
+
+fn str_arg(val: &Value) -> Result<&str> {
+    match val {
+        Value::Str(s) => Ok(s),
+        val => Err(wrong_type("Str", val)),
+    }
+}
+
+fn num_arg(val: &Value) -> Result<f64> {
+    match val {
+        Value::Num(x) => Ok(*x),
+        Value::Int(x) => Ok(*x as f64),
+        val => Err(wrong_type("Num", val)),
+    }
+}
+
+fn str_array<'a>(parts: impl Iterator<Item = &'a str>) -> Value {
+    Value::new_array(parts.map(Value::new_str).collect())
+}
+
+fn lines(vals: &[Value]) -> Result<Value> {
+    Ok(str_array(str_arg(&vals[0])?.lines()))
+}
+
+fn split_whitespace(vals: &[Value]) -> Result<Value> {
+    Ok(str_array(str_arg(&vals[0])?.split_whitespace()))
+}
+
+fn trim(vals: &[Value]) -> Result<Value> {
+    Ok(Value::new_str(str_arg(&vals[0])?.trim()))
+}
+
+fn trim_start(vals: &[Value]) -> Result<Value> {
+    Ok(Value::new_str(str_arg(&vals[0])?.trim_start()))
+}
+
+fn trim_end(vals: &[Value]) -> Result<Value> {
+    Ok(Value::new_str(str_arg(&vals[0])?.trim_end()))
+}
+
+/// Remove a prefix from a string, which is returned unchanged if it doesn't start with it.
+fn strip_prefix(vals: &[Value]) -> Result<Value> {
+    let s = str_arg(&vals[0])?;
+    let prefix = str_arg(&vals[1])?;
+    Ok(Value::new_str(s.strip_prefix(prefix).unwrap_or(s)))
+}
+
+/// Remove a suffix from a string, which is returned unchanged if it doesn't end with it.
+fn strip_suffix(vals: &[Value]) -> Result<Value> {
+    let s = str_arg(&vals[0])?;
+    let suffix = str_arg(&vals[1])?;
+    Ok(Value::new_str(s.strip_suffix(suffix).unwrap_or(s)))
+}
+
+/// The bytes held by an array of whole numbers between 0 and 255.
+/// There's no type for raw bytes, so arrays like these stand in for them.
+fn byte_arg(val: &Value) -> Result<Vec<u8>> {
+    elements(val)?
+        .iter()
+        .map(|val| match val {
+            Value::Num(x) if x.fract() == 0.0 && (0.0..=255.0).contains(x) => Ok(*x as u8),
+            Value::Int(x) if (0..=255).contains(x) => Ok(*x as u8),
+            val => {
+                let msg = format!("Expected a byte between 0 and 255, found {}", val);
+                Err(ValueError::InvalidArgument(msg).into())
+            }
+        })
+        .collect()
+}
+
+/// The bytes of a string's UTF-8 encoding, as an array of numbers.
+fn to_utf8_bytes(vals: &[Value]) -> Result<Value> {
+    let bytes = str_arg(&vals[0])?.bytes();
+    Ok(Value::new_array(
+        bytes.map(|b| Value::Int(b.into())).collect(),
+    ))
+}
+
+/// Decode an array of bytes as UTF-8, failing if they aren't valid.
+fn from_utf8(vals: &[Value]) -> Result<Value> {
+    let s = String::from_utf8(byte_arg(&vals[0])?).map_err(|err| {
+        let msg = format!("Invalid UTF-8: {}", err.utf8_error());
+        ValueError::InvalidArgument(msg)
+    })?;
+    Ok(Value::new_str(s))
+}
+
+/// Decode an array of bytes as UTF-8, replacing invalid sequences with U+FFFD.
+fn from_utf8_lossy(vals: &[Value]) -> Result<Value> {
+    let bytes = byte_arg(&vals[0])?;
+    Ok(Value::new_str(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn is_valid_utf8(vals: &[Value]) -> Result<Value> {
+    let bytes = byte_arg(&vals[0])?;
+    Ok(Value::Bool(std::str::from_utf8(&bytes).is_ok()))
+}
+
+fn bigint(vals: &[Value]) -> Result<Value> {
+    let x = match &vals[0] {
+        Value::BigInt(_) => return Ok(vals[0].clone()),
+        Value::Int(x) => Some(BigInt::from(*x)),
+        Value::Num(x) if x.fract() == 0.0 => BigInt::from_f64(*x),
+        Value::Str(s) => s.trim().parse::<BigInt>().ok(),
+        _ => None,
+    };
+    x.map(Value::from)
+        .ok_or_else(|| wrong_type("integral Num or numeric Str", &vals[0]))
+}
+
+fn decimal(vals: &[Value]) -> Result<Value> {
+    let x = match &vals[0] {
+        Value::Decimal(_) => return Ok(vals[0].clone()),
+        Value::BigInt(x) => Some(Decimal::from((**x).clone())),
+        Value::Int(x) => Some(Decimal::from(BigInt::from(*x))),
+        Value::Num(x) => x.to_string().parse().ok(),
+        Value::Str(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+    x.map(Value::from)
+        .ok_or_else(|| wrong_type("Num or decimal number Str", &vals[0]))
+}
+
+/// Parse a string as a number, or return null if it isn't one.
+fn try_num(vals: &[Value]) -> Result<Value> {
+    let x = str_arg(&vals[0])?.trim().parse::<f64>().ok();
+    Ok(x.filter(|x| x.is_finite()).map_or(Value::Null, Value::Num))
+}
+
+/// Parse a string as a whole number, or return null if it isn't one.
+/// Numbers too big to fit in an int are returned as big integers.
+fn try_int(vals: &[Value]) -> Result<Value> {
+    let x = match str_arg(&vals[0])?.trim().parse::<BigInt>() {
+        Ok(x) => x,
+        Err(_) => return Ok(Value::Null),
+    };
+    match x.to_i64() {
+        Some(n) => Ok(Value::Int(n)),
+        None => Ok(x.into()),
+    }
+}
+
+/// Match a string against a pattern of text and `{name}` placeholders, returning a map
+/// of what each placeholder matched, or null if the string doesn't match.
+fn parse(vals: &[Value]) -> Result<Value> {
+    let s = str_arg(&vals[0])?;
+    let pattern = str_arg(&vals[1])?;
+    let captures = pattern::parse(s, pattern).map_err(ValueError::InvalidArgument)?;
+    Ok(captures.map_or(Value::Null, Value::new_map_from))
+}
+
+/// Split what a scanner found at the start of a string, after any whitespace,
+/// from the rest of it as `[found, rest]`, or return null if it found nothing.
+fn scanned(s: &str, scan: impl FnOnce(&str) -> Option<(Value, usize)>) -> Value {
+    let s = s.trim_start();
+    match scan(s) {
+        Some((val, len)) => Value::new_array(vec![val, Value::new_str(&s[len..])]),
+        None => Value::Null,
+    }
+}
+
+fn scan_num(vals: &[Value]) -> Result<Value> {
+    Ok(scanned(str_arg(&vals[0])?, pattern::scan_num))
+}
+
+fn scan_word(vals: &[Value]) -> Result<Value> {
+    let scan_word = |s: &str| {
+        let len = pattern::scan_word(s)?;
+        Some((Value::new_str(&s[..len]), len))
+    };
+    Ok(scanned(str_arg(&vals[0])?, scan_word))
+}
+
+/// Parse a string as JSON, or return null if it isn't valid.
+fn try_json(vals: &[Value]) -> Result<Value> {
+    Ok(json::decode(str_arg(&vals[0])?).unwrap_or(Value::Null))
+}
+
+fn decimal_round(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let x = match &vals[0] {
+        Value::Decimal(x) => x,
+        val => return Err(wrong_type("Decimal", val)),
+    };
+    let places = vm.as_index(&vals[1])? as u32;
+    let mode = match &vals[2] {
+        Value::Str(s) => s.parse::<Rounding>().map_err(|()| {
+            ValueError::InvalidArgument(format!(
+                "Unknown rounding mode '{}': expected one of \
+                 half_even, half_up, down, up, floor or ceiling",
+                s
+            ))
+        })?,
+        val => return Err(wrong_type("Str", val)),
+    };
+    Ok(x.round(places, mode).into())
+}
+
+/// Limit a value to the range between two others, inclusive.
+fn clamp(vals: &[Value]) -> Result<Value> {
+    let (x, lo, hi) = (&vals[0], &vals[1], &vals[2]);
+    if lo.cmp(hi)? == Ordering::Greater {
+        return Err(ValueError::InvalidArgument(format!(
+            "Lower bound {} is greater than upper bound {}",
+            lo, hi
+        ))
+        .into());
+    }
+    let clamped = if x.cmp(lo)? == Ordering::Less {
+        lo
+    } else if x.cmp(hi)? == Ordering::Greater {
+        hi
+    } else {
+        x
+    };
+    Ok(clamped.clone())
+}
+
+/// The number a fraction `t` of the way from `a` to `b`.
+fn lerp(vals: &[Value]) -> Result<Value> {
+    let (a, b, t) = (num_arg(&vals[0])?, num_arg(&vals[1])?, num_arg(&vals[2])?);
+    Ok(Value::Num(a + (b - a) * t))
+}
+
+/// -1, 0 or 1, depending on whether a number is negative, zero or positive.
+fn sign(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Num(x) if x.is_nan() => Ok(Value::Num(*x)),
+        x @ (Value::Num(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_)) => {
+            Ok(ordering(x.cmp(&Value::Int(0))?))
+        }
+        val => Err(wrong_type("Num", val)),
+    }
+}
+
+/// Remove the fractional part of a number, rounding it towards zero.
+fn trunc(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Num(x) => Ok(Value::Num(x.trunc())),
+        Value::Int(_) | Value::BigInt(_) => Ok(vals[0].clone()),
+        Value::Decimal(x) => Ok(x.round(0, Rounding::Down).into()),
+        val => Err(wrong_type("Num", val)),
+    }
+}
+
+/// The angle from the x axis to the point (x, y), given as `atan2(y, x)`.
+fn atan2(vals: &[Value]) -> Result<Value> {
+    let (y, x) = (num_arg(&vals[0])?, num_arg(&vals[1])?);
+    Ok(Value::Num(y.atan2(x)))
+}
+
+/// The length of the hypotenuse of a right triangle with the given sides.
+fn hypot(vals: &[Value]) -> Result<Value> {
+    let (x, y) = (num_arg(&vals[0])?, num_arg(&vals[1])?);
+    Ok(Value::Num(x.hypot(y)))
+}
+
+/// The elements of a non-empty array of numbers, for computing the statistic `what` of.
+fn samples(val: &Value, what: &str) -> Result<Vec<f64>> {
+    let xs = elements(val)?
+        .iter()
+        .map(num_arg)
+        .collect::<Result<Vec<_>>>()?;
+    if xs.is_empty() {
+        let msg = format!("Can't take the {} of an empty array", what);
+        return Err(ValueError::InvalidArgument(msg).into());
+    }
+    Ok(xs)
+}
+
+fn mean_of(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// The value below which `p` percent of the sorted `xs` lie,
+/// interpolating between the two closest elements.
+fn percentile_of(mut xs: Vec<f64>, p: f64) -> f64 {
+    xs.sort_by(f64::total_cmp);
+    let rank = p / 100.0 * (xs.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    xs[below] + (xs[above] - xs[below]) * rank.fract()
+}
+
+fn mean(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Num(mean_of(&samples(&vals[0], "mean")?)))
+}
+
+fn median(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Num(percentile_of(
+        samples(&vals[0], "median")?,
+        50.0,
+    )))
+}
+
+/// The population standard deviation of an array of numbers.
+fn stddev(vals: &[Value]) -> Result<Value> {
+    let xs = samples(&vals[0], "standard deviation")?;
+    let mean = mean_of(&xs);
+    let variance = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / xs.len() as f64;
+    Ok(Value::Num(variance.sqrt()))
+}
+
+fn percentile(vals: &[Value]) -> Result<Value> {
+    let xs = samples(&vals[0], "percentile")?;
+    let p = num_arg(&vals[1])?;
+    if !(0.0..=100.0).contains(&p) {
+        let msg = format!("Percentile {} isn't between 0 and 100", p);
+        return Err(ValueError::InvalidArgument(msg).into());
+    }
+    Ok(Value::Num(percentile_of(xs, p)))
+}
+
+fn push(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Array(a) => {
+            a.borrow_mut()?.push(vals[1].clone());
+            Ok(Value::Null)
+        }
+        val => Err(wrong_type("Array", val)),
+    }
+}
+
+/// The index of the first element of the sorted `items` for which `before` is false.
+fn partition_point(
+    items: &[Value],
+    before: impl Fn(Ordering) -> bool,
+    val: &Value,
+) -> Result<usize> {
+    let (mut lo, mut hi) = (0, items.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if before(items[mid].cmp(val)?) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(lo)
+}
+
+/// Look for a value in a sorted array, returning ok(index) if it's there,
+/// and err(index) with the index it would have to be inserted at otherwise.
+fn binary_search(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Array(a) => {
+            let a = a.borrow();
+            let i = partition_point(&a, |ord| ord == Ordering::Less, &vals[1])?;
+            let i_val = Rc::new(Value::Int(i as i64));
+            if a.get(i) == Some(&vals[1]) {
+                Ok(Value::Ok(i_val))
+            } else {
+                Ok(Value::Err(i_val))
+            }
+        }
+        val => Err(wrong_type("Array", val)),
+    }
+}
+
+/// Insert a value into a sorted array after any equal elements, returning its index.
+fn sorted_insert(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Array(a) => {
+            let mut a = a.borrow_mut()?;
+            let i = partition_point(&a, |ord| ord != Ordering::Greater, &vals[1])?;
+            a.insert(i, vals[1].clone());
+            Ok(Value::Int(i as i64))
+        }
+        val => Err(wrong_type("Array", val)),
+    }
+}
+
+/// Remove consecutive repeated elements of an array, which removes every duplicate
+/// when it's sorted.
+fn unique(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Array(a) => {
+            a.borrow_mut()?.dedup();
+            Ok(Value::Null)
+        }
+        val => Err(wrong_type("Array", val)),
+    }
+}
+
+/// Sort an array in place, keeping equal elements in the order they were in.
+fn sort(vals: &[Value]) -> Result<Value> {
+    sort_array(&vals[0], |a, b| Ok(a.cmp(b)?))
+}
+
+/// Sort an array in place by a function comparing two elements,
+/// which returns a negative number, zero, or a positive number
+/// if the first one goes before, alongside, or after the second one.
+fn sort_by(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    sort_array(&vals[0], |a, b| {
+        let order = vm.call(vals[1].clone(), vec![a.clone(), b.clone()])?;
+        match order {
+            Value::Num(x) => Ok(x.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+            Value::Int(x) => Ok(x.cmp(&0)),
+            val => Err(wrong_type("Num", &val)),
+        }
+    })
+}
+
+/// Sort the elements of an array, then store them back into it.
+/// The array isn't borrowed while sorting, in case comparing elements looks at it.
+fn sort_array(
+    val: &Value,
+    mut cmp: impl FnMut(&Value, &Value) -> Result<Ordering>,
+) -> Result<Value> {
+    let a = match val {
+        Value::Array(a) => a,
+        val => return Err(wrong_type("Array", val)),
+    };
+    // Check this before comparing anything, so that sorting a frozen array fails straight away.
+    a.borrow_mut()?;
+    let sorted = merge_sort(a.borrow().clone(), &mut cmp)?;
+    *a.borrow_mut()? = sorted;
     Ok(Value::Null)
 }
 
-pub fn load_libraries(vm: &mut VirtualMachine) {
+/// A stable sort which gives up as soon as comparing two values fails.
+/// Unlike the standard library's sorts, it's fine with comparisons which aren't consistent.
+fn merge_sort(
+    mut vals: Vec<Value>,
+    cmp: &mut impl FnMut(&Value, &Value) -> Result<Ordering>,
+) -> Result<Vec<Value>> {
+    if vals.len() <= 1 {
+        return Ok(vals);
+    }
+    let right = vals.split_off(vals.len() / 2);
+    let left = merge_sort(vals, cmp)?;
+    let right = merge_sort(right, cmp)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if cmp(r, l)? == Ordering::Less {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn ordering(order: Ordering) -> Value {
+    Value::Int(order as i64)
+}
+
+/// Compare two strings without regard to case, returning -1, 0 or 1 like `sort_by` expects.
+fn cmp_ignore_case(vals: &[Value]) -> Result<Value> {
+    let a = str_arg(&vals[0])?.to_lowercase();
+    let b = str_arg(&vals[1])?.to_lowercase();
+    Ok(ordering(a.cmp(&b)))
+}
+
+/// Compare two strings the way people would, so that numbers inside them are compared
+/// by their value, and "file2" goes before "file10". Returns -1, 0 or 1 like `sort_by` expects.
+fn natural_cmp(vals: &[Value]) -> Result<Value> {
+    let a = str_arg(&vals[0])?;
+    let b = str_arg(&vals[1])?;
+    Ok(ordering(natural_order(a, b)))
+}
+
+fn natural_order(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (ca, cb) = match (a.chars().next(), b.chars().next()) {
+            (Some(ca), Some(cb)) => (ca, cb),
+            (ca, cb) => return ca.is_some().cmp(&cb.is_some()),
+        };
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let (da, rest_a) = split_digits(a);
+            let (db, rest_b) = split_digits(b);
+            let (na, nb) = (da.trim_start_matches('0'), db.trim_start_matches('0'));
+            // Longer numbers are bigger, once leading zeros are out of the way.
+            // Equal numbers with fewer leading zeros go first.
+            let order = na
+                .len()
+                .cmp(&nb.len())
+                .then_with(|| na.cmp(nb))
+                .then_with(|| da.len().cmp(&db.len()));
+            if order != Ordering::Equal {
+                return order;
+            }
+            a = rest_a;
+            b = rest_b;
+        } else if ca != cb {
+            return ca.cmp(&cb);
+        } else {
+            a = &a[ca.len_utf8()..];
+            b = &b[cb.len_utf8()..];
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// The elements of an array from index `start` up to, but not including, `end`.
+fn slice(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let a = elements(&vals[0])?;
+    let (start, end) = (vm.as_index(&vals[1])?, vm.as_index(&vals[2])?);
+    if end > a.len() || start > end {
+        let index = if end > a.len() { end } else { start };
+        return Err(ValueError::OutOfBounds {
+            index: index as f64,
+            len: a.len(),
+        }
+        .into());
+    }
+    Ok(Value::new_array(a[start..end].to_vec()))
+}
+
+/// The size of the pieces `chunks` and `windows` split arrays into, which can't be zero.
+fn piece_size(vm: &VirtualMachine, val: &Value) -> Result<usize> {
+    match vm.as_index(val)? {
+        0 => Err(ValueError::InvalidArgument("Size must be at least 1".to_owned()).into()),
+        n => Ok(n),
+    }
+}
+
+/// Split an array into arrays of `n` consecutive elements, except for the last one,
+/// which holds whatever elements are left over.
+fn chunks(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let a = elements(&vals[0])?;
+    let n = piece_size(vm, &vals[1])?;
+    let chunks = a.chunks(n).map(|c| Value::new_array(c.to_vec()));
+    Ok(Value::new_array(chunks.collect()))
+}
+
+/// Every run of `n` consecutive elements of an array, overlapping each other.
+fn windows(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let a = elements(&vals[0])?;
+    let n = piece_size(vm, &vals[1])?;
+    let windows = a.windows(n).map(|w| Value::new_array(w.to_vec()));
+    Ok(Value::new_array(windows.collect()))
+}
+
+/// Where a value first appears in an array, or a substring in a string, or null if it doesn't.
+/// Indices into strings count characters, like `get` does.
+fn index_of(vals: &[Value]) -> Result<Value> {
+    position(
+        vals,
+        |a, needle| a.iter().position(|val| val == needle),
+        |s, p| s.find(p),
+    )
+}
+
+/// Where a value last appears in an array, or a substring in a string, or null if it doesn't.
+fn last_index_of(vals: &[Value]) -> Result<Value> {
+    position(
+        vals,
+        |a, needle| a.iter().rposition(|val| val == needle),
+        |s, p| s.rfind(p),
+    )
+}
+
+fn position(
+    vals: &[Value],
+    in_array: fn(&[Value], &Value) -> Option<usize>,
+    in_str: fn(&str, &str) -> Option<usize>,
+) -> Result<Value> {
+    let i = match &vals[0] {
+        Value::Array(a) => in_array(&a.borrow(), &vals[1]),
+        Value::Str(s) => {
+            let byte = in_str(s, str_arg(&vals[1])?);
+            byte.map(|byte| s[..byte].chars().count())
+        }
+        val => return Err(wrong_type("Array or Str", val)),
+    };
+    Ok(i.map_or(Value::Null, |i| Value::Int(i as i64)))
+}
+
+/// The first element of an array a function returns a truthy value for, or null if there's none.
+fn find(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    for val in elements(&vals[0])? {
+        if vm.call(vals[1].clone(), vec![val.clone()])?.is_truthy() {
+            return Ok(val);
+        }
+    }
+    Ok(Value::Null)
+}
+
+/// The index of the first element of an array a function returns a truthy value for,
+/// or null if there's none.
+fn find_index(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    for (i, val) in elements(&vals[0])?.into_iter().enumerate() {
+        if vm.call(vals[1].clone(), vec![val])?.is_truthy() {
+            return Ok(Value::Int(i as i64));
+        }
+    }
+    Ok(Value::Null)
+}
+
+/// A copy of an array with its elements in the opposite order,
+/// or of a string with its characters in the opposite order.
+fn reverse(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Array(a) => Ok(Value::new_array(a.borrow().iter().rev().cloned().collect())),
+        Value::Str(s) => Ok(Value::new_str(s.chars().rev().collect::<String>())),
+        val => Err(wrong_type("Array or Str", val)),
+    }
+}
+
+/// The numbers from a start (0 if only an end is given) up to, but not including, an end,
+/// counting in steps of 1 or of the given size, which can be negative to count down.
+fn range(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let whole_arg = |val| num_arg(val).and_then(|x| vm.whole(x));
+    let (start, end) = match vals {
+        [end] => (0.0, whole_arg(end)?),
+        [start, end, ..] => (whole_arg(start)?, whole_arg(end)?),
+        [] => unreachable!(),
+    };
+    let step = vals.get(2).map(whole_arg).transpose()?.unwrap_or(1.0);
+    if step == 0.0 || !step.is_finite() {
+        let msg = format!("Step must be a nonzero number, found {}", step);
+        return Err(ValueError::InvalidArgument(msg).into());
+    }
+    let (start, end, step) = (start as i64, end as i64, step as i64);
+    let mut nums = Vec::new();
+    let mut x = start;
+    while (step > 0 && x < end) || (step < 0 && x > end) {
+        nums.push(Value::Int(x));
+        x = match x.checked_add(step) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(Value::new_array(nums))
+}
+
+fn pop(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Array(a) => Ok(a.borrow_mut()?.pop().unwrap_or(Value::Null)),
+        val => Err(wrong_type("Array", val)),
+    }
+}
+
+fn map(_: &[Value]) -> Result<Value> {
+    Ok(Value::new_map())
+}
+
+fn get(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let val = match &vals[0] {
+        Value::Str(s) => s
+            .chars()
+            .nth(vm.as_index(&vals[1])?)
+            .map(|c| Value::new_str(c.to_string())),
+        Value::Array(a) => a.borrow().get(vm.as_index(&vals[1])?).cloned(),
+        Value::Map(m) => m.borrow().get(&Key::new(vals[1].clone())?).cloned(),
+        val => return Err(wrong_type("Str, Array or Map", val)),
+    };
+    // Missing elements are null, unless a default to return instead is given.
+    let default = || vals.get(2).cloned().unwrap_or(Value::Null);
+    Ok(val.unwrap_or_else(default))
+}
+
+fn set(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let val = vals[2].clone();
+    vm.set_index(&vals[0], &vals[1], val.clone())?;
+    Ok(val)
+}
+
+fn remove(vals: &[Value]) -> Result<Value> {
+    let key = Key::new(vals[1].clone())?;
+    match &vals[0] {
+        Value::Map(m) => Ok(m.borrow_mut()?.shift_remove(&key).unwrap_or(Value::Null)),
+        Value::Set(s) => Ok(Value::Bool(s.borrow_mut()?.shift_remove(&key))),
+        val => Err(wrong_type("Map or Set", val)),
+    }
+}
+
+fn keys(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Map(m) => {
+            let keys = m.borrow().keys().map(|k| k.value().clone()).collect();
+            Ok(Value::new_array(keys))
+        }
+        val => Err(wrong_type("Map", val)),
+    }
+}
+
+fn map_entries(val: &Value) -> Result<IndexMap<Key, Value>> {
+    match val {
+        Value::Map(m) => Ok(m.borrow().clone()),
+        val => Err(wrong_type("Map", val)),
+    }
+}
+
+/// A new map with the entries of two others, preferring the second's values for keys in both.
+fn merge(vals: &[Value]) -> Result<Value> {
+    let mut merged = map_entries(&vals[0])?;
+    merged.extend(map_entries(&vals[1])?);
+    Ok(Value::new_map_from(merged))
+}
+
+/// The entries of a map, as an array of `[key, value]` pairs.
+fn entries(vals: &[Value]) -> Result<Value> {
+    let pairs = map_entries(&vals[0])?
+        .into_iter()
+        .map(|(key, val)| Value::new_array(vec![key.value().clone(), val]))
+        .collect();
+    Ok(Value::new_array(pairs))
+}
+
+/// Build a map from an array of `[key, value]` pairs, the opposite of `entries`.
+fn from_entries(vals: &[Value]) -> Result<Value> {
+    let mut map = IndexMap::new();
+    for pair in elements(&vals[0])? {
+        match &pair {
+            Value::Array(a) if a.borrow().len() == 2 => {
+                let a = a.borrow();
+                map.insert(Key::new(a[0].clone())?, a[1].clone());
+            }
+            _ => {
+                let msg = format!("Expected a [key, value] pair, found {}", pair);
+                return Err(ValueError::InvalidArgument(msg).into());
+            }
+        }
+    }
+    Ok(Value::new_map_from(map))
+}
+
+fn to_set(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Array(a) => {
+            let set = a
+                .borrow()
+                .iter()
+                .cloned()
+                .map(Key::new)
+                .collect::<std::result::Result<IndexSet<_>, _>>()?;
+            Ok(Value::new_set(set))
+        }
+        Value::Set(_) => Ok(vals[0].clone()),
+        val => Err(wrong_type("Array", val)),
+    }
+}
+
+fn to_array(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Set(s) => {
+            let vals = s.borrow().iter().map(|k| k.value().clone()).collect();
+            Ok(Value::new_array(vals))
+        }
+        Value::Array(_) => Ok(vals[0].clone()),
+        val => Err(wrong_type("Set", val)),
+    }
+}
+
+fn add(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Set(s) => {
+            let added = s.borrow_mut()?.insert(Key::new(vals[1].clone())?);
+            Ok(Value::Bool(added))
+        }
+        val => Err(wrong_type("Set", val)),
+    }
+}
+
+fn contains(vals: &[Value]) -> Result<Value> {
+    let found = match &vals[0] {
+        Value::Array(a) => a.borrow().contains(&vals[1]),
+        Value::Map(m) => m.borrow().contains_key(&Key::new(vals[1].clone())?),
+        Value::Set(s) => s.borrow().contains(&Key::new(vals[1].clone())?),
+        val => return Err(wrong_type("Array, Map or Set", val)),
+    };
+    Ok(Value::Bool(found))
+}
+
+/// A number which is the same for every two values that are the same map key.
+/// It doesn't change between runs, but may between versions of Oxide.
+fn hash(vals: &[Value]) -> Result<Value> {
+    let key = Key::new(vals[0].clone())?;
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    // Keep as many bits as a number can hold exactly.
+    Ok(Value::Num((hasher.finish() >> 11) as f64))
+}
+
+fn freeze(vals: &[Value]) -> Result<Value> {
+    Ok(vals[0].freeze())
+}
+
+fn is_frozen(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(vals[0].is_frozen()))
+}
+
+fn weak(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Weak(WeakRef::new(&vals[0])?))
+}
+
+/// The value behind a weak reference, or null if nothing else keeps it alive anymore.
+fn deref(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Weak(w) => Ok(w.upgrade().unwrap_or(Value::Null)),
+        val => Err(wrong_type("Weak", val)),
+    }
+}
+
+fn ok(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Ok(Rc::new(vals[0].clone())))
+}
+
+fn err(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Err(Rc::new(vals[0].clone())))
+}
+
+fn is_null(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(vals[0], Value::Null)))
+}
+
+/// Whether a value is a number of any kind: a float, an int, a big integer or a decimal.
+fn is_num(vals: &[Value]) -> Result<Value> {
+    let is_num = matches!(
+        vals[0],
+        Value::Num(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_)
+    );
+    Ok(Value::Bool(is_num))
+}
+
+fn is_str(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(vals[0], Value::Str(_))))
+}
+
+fn is_bool(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(vals[0], Value::Bool(_))))
+}
+
+fn is_array(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(vals[0], Value::Array(_))))
+}
+
+fn is_map(vals: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(matches!(vals[0], Value::Map(_))))
+}
+
+/// Whether a value can be called, whether it's written in a script or built in.
+fn is_fn(vals: &[Value]) -> Result<Value> {
+    let is_fn = matches!(vals[0], Value::Function { .. } | Value::NativeFn { .. });
+    Ok(Value::Bool(is_fn))
+}
+
+fn is_ok(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Ok(_) => Ok(Value::Bool(true)),
+        Value::Err(_) => Ok(Value::Bool(false)),
+        val => Err(wrong_type("Result", val)),
+    }
+}
+
+fn is_err(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Ok(_) => Ok(Value::Bool(false)),
+        Value::Err(_) => Ok(Value::Bool(true)),
+        val => Err(wrong_type("Result", val)),
+    }
+}
+
+/// The value inside an `ok` result, or `default` for an `err` result or null.
+fn unwrap_or(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Ok(val) => Ok((**val).clone()),
+        Value::Err(_) | Value::Null => Ok(vals[1].clone()),
+        val => Err(wrong_type("Result", val)),
+    }
+}
+
+/// Stop the script with a runtime error carrying `message` and the current call stack.
+fn panic(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    Err(crate::vm::Error::Panic {
+        message: vals[0].to_string(),
+        backtrace: vm.backtrace(),
+    })
+}
+
+fn store_open(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Str(path) => {
+            let store = Store::open(path.as_str().into())?;
+            Ok(Value::new_host("Store", store))
+        }
+        val => Err(wrong_type("Str", val)),
+    }
+}
+
+/// The store and string key passed to a store native.
+fn store_entry(vals: &[Value]) -> Result<(&Store, &str)> {
+    let store = vals[0]
+        .host_object::<Store>()
+        .ok_or_else(|| wrong_type("Store", &vals[0]))?;
+    match &vals[1] {
+        Value::Str(key) => Ok((store, key)),
+        val => Err(wrong_type("Str", val)),
+    }
+}
+
+fn store_get(vals: &[Value]) -> Result<Value> {
+    let (store, key) = store_entry(vals)?;
+    Ok(store.get(key).unwrap_or(Value::Null))
+}
+
+fn store_set(vals: &[Value]) -> Result<Value> {
+    let (store, key) = store_entry(vals)?;
+    store.set(key.to_owned(), vals[2].clone())?;
+    Ok(vals[2].clone())
+}
+
+fn store_delete(vals: &[Value]) -> Result<Value> {
+    let (store, key) = store_entry(vals)?;
+    Ok(Value::Bool(store.delete(key)?))
+}
+
+fn store_keys(vals: &[Value]) -> Result<Value> {
+    let store = vals[0]
+        .host_object::<Store>()
+        .ok_or_else(|| wrong_type("Store", &vals[0]))?;
+    let keys = store.keys().into_iter().map(Value::new_str).collect();
+    Ok(Value::new_array(keys))
+}
+
+/// Color or decorate text, given a string of space-separated style names.
+/// The text is returned unchanged when the output isn't a terminal.
+fn path_value(path: &Path) -> Value {
+    Value::new_str(path.to_string_lossy().into_owned())
+}
+
+/// The paths matching a pattern, in which `*` and `?` match within a file name
+/// and `**` matches any number of directories.
+fn glob(vals: &[Value]) -> Result<Value> {
+    let pattern = str_arg(&vals[0])?;
+    let paths = glob::glob(pattern).map_err(|err| {
+        ValueError::InvalidArgument(format!("Invalid pattern '{}': {}", pattern, err))
+    })?;
+    let mut matches = Vec::new();
+    for path in paths {
+        matches.push(path_value(&path.map_err(io::Error::from)?));
+    }
+    Ok(Value::new_array(matches))
+}
+
+/// Call a function with the path of every file inside a directory and its subdirectories,
+/// in alphabetical order.
+fn walk_dir(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let mut files = Vec::new();
+    files_under(Path::new(str_arg(&vals[0])?), &mut files)?;
+    for file in files {
+        vm.call(vals[1].clone(), vec![path_value(&file)])?;
+    }
+    Ok(Value::Null)
+}
+
+fn files_under(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(fs::DirEntry::file_name);
+    for entry in entries {
+        // Symbolic links aren't followed, so links to a parent directory can't loop forever.
+        if entry.file_type()?.is_dir() {
+            files_under(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Call a function whenever the process receives a signal, like "INT" or "TERM",
+/// instead of being stopped by it.
+fn on_signal(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let name = str_arg(&vals[0])?;
+    let flag = signals::catch(name).ok_or_else(|| {
+        ValueError::InvalidArgument(format!(
+            "Unknown signal '{}': expected one of INT, TERM, HUP, USR1 or USR2",
+            name
+        ))
+    })?;
+    vm.on_flag(flag, vals[1].clone());
+    Ok(Value::Null)
+}
+
+/// Start describing the command line options of a script.
+fn flags_new(vals: &[Value]) -> Result<Value> {
+    let description = vals.first().map(str_arg).transpose()?.unwrap_or("");
+    Ok(Value::new_host("Flags", Flags::new(description.to_owned())))
+}
+
+fn flags_arg(val: &Value) -> Result<&Flags> {
+    val.host_object::<Flags>()
+        .ok_or_else(|| wrong_type("Flags", val))
+}
+
+/// Add an option, given by its name (like "--verbose"), the kind of value it takes,
+/// and optionally a line of help describing it.
+fn flags_add(vals: &[Value]) -> Result<Value> {
+    let flags = flags_arg(&vals[0])?;
+    let name = str_arg(&vals[1])?;
+    let kind = str_arg(&vals[2])?.parse::<Kind>()?;
+    let help = vals.get(3).map(str_arg).transpose()?.unwrap_or("");
+    flags.add(name, kind, help.to_owned())?;
+    Ok(Value::Null)
+}
+
+/// Parse an array of arguments into a map of options.
+/// If they ask for help, it's printed instead, and null is returned.
+fn flags_parse(vals: &[Value]) -> Result<Value> {
+    let flags = flags_arg(&vals[0])?;
+    let args = elements(&vals[1])?
+        .iter()
+        .map(|arg| str_arg(arg).map(str::to_owned))
+        .collect::<Result<Vec<_>>>()?;
+    match flags.parse(&args)? {
+        Some(parsed) => Ok(Value::new_map_from(parsed)),
+        None => {
+            println!("{}", flags.help());
+            Ok(Value::Null)
+        }
+    }
+}
+
+fn flags_help(vals: &[Value]) -> Result<Value> {
+    Ok(Value::new_str(flags_arg(&vals[0])?.help()))
+}
+
+fn style(vals: &[Value]) -> Result<Value> {
+    match &vals[1] {
+        Value::Str(styles) => {
+            let text =
+                term::style(&vals[0].to_string(), styles).map_err(ValueError::InvalidArgument)?;
+            Ok(Value::new_str(text))
+        }
+        val => Err(wrong_type("Str", val)),
+    }
+}
+
+fn clear_screen(_vals: &[Value]) -> Result<Value> {
+    term::control("\x1b[2J\x1b[H")?;
+    Ok(Value::Null)
+}
+
+/// Move the cursor to a row and column, counting from 0 at the top left corner.
+fn move_cursor(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let (row, col) = (vm.as_index(&vals[0])?, vm.as_index(&vals[1])?);
+    term::control(&format!("\x1b[{};{}H", row + 1, col + 1))?;
+    Ok(Value::Null)
+}
+
+fn term_width(_vals: &[Value]) -> Result<Value> {
+    Ok(Value::Int(term::width() as i64))
+}
+
+fn is_terminal(_vals: &[Value]) -> Result<Value> {
+    Ok(Value::Bool(term::enabled()))
+}
+
+/// How deeply log messages show nested aggregates, so that logging one can't flood the log.
+const LOG_DEPTH: usize = 4;
+
+fn log_message(val: &Value) -> String {
+    val.display_depth(LOG_DEPTH).to_string()
+}
+
+fn log_debug(vals: &[Value]) -> Result<Value> {
+    logging::log(Level::Debug, &log_message(&vals[0]))?;
+    Ok(Value::Null)
+}
+
+fn log_info(vals: &[Value]) -> Result<Value> {
+    logging::log(Level::Info, &log_message(&vals[0]))?;
+    Ok(Value::Null)
+}
+
+fn log_warn(vals: &[Value]) -> Result<Value> {
+    logging::log(Level::Warn, &log_message(&vals[0]))?;
+    Ok(Value::Null)
+}
+
+fn log_error(vals: &[Value]) -> Result<Value> {
+    logging::log(Level::Error, &log_message(&vals[0]))?;
+    Ok(Value::Null)
+}
+
+fn log_set_level(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Str(name) => {
+            let level = Level::parse(name).ok_or_else(|| {
+                ValueError::InvalidArgument(format!("Unknown log level '{}'", name))
+            })?;
+            logging::set_level(level);
+            Ok(Value::Null)
+        }
+        val => Err(wrong_type("Str", val)),
+    }
+}
+
+/// Send log messages to a file, or back to standard error when given null.
+fn log_to_file(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Str(path) => logging::set_file(Some(path))?,
+        Value::Null => logging::set_file(None)?,
+        val => return Err(wrong_type("Str or Null", val)),
+    }
+    Ok(Value::Null)
+}
+
+/// Read a line with editing and history, or null once the input ends.
+fn readline(vals: &[Value]) -> Result<Value> {
+    let prompt = vals.first().map(str_arg).transpose()?.unwrap_or("");
+    match readline::read(prompt) {
+        Ok(Some(line)) => Ok(Value::new_str(line)),
+        Ok(None) => Ok(Value::Null),
+        Err(ReadlineError::Interrupted) => Err(crate::vm::Error::Interrupted),
+        Err(err) => Err(readline::into_io_error(err).into()),
+    }
+}
+
+fn readline_history(vals: &[Value]) -> Result<Value> {
+    readline::set_history_file(PathBuf::from(str_arg(&vals[0])?))?;
+    Ok(Value::Null)
+}
+
+fn sb_new(_: &[Value]) -> Result<Value> {
+    Ok(Value::Builder(Default::default()))
+}
+
+fn sb_push(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Builder(b) => {
+            match &vals[1] {
+                Value::Str(s) => b.borrow_mut().push_str(s),
+                val => b.borrow_mut().push_str(&val.to_string()),
+            }
+            Ok(vals[0].clone())
+        }
+        val => Err(wrong_type("StringBuilder", val)),
+    }
+}
+
+fn sb_build(vals: &[Value]) -> Result<Value> {
+    match &vals[0] {
+        Value::Builder(b) => Ok(Value::new_str(b.borrow().as_str())),
+        val => Err(wrong_type("StringBuilder", val)),
+    }
+}
+
+/// A copy of the elements of an array, so functions called on them can't invalidate the iteration.
+fn elements(val: &Value) -> Result<Vec<Value>> {
+    match val {
+        Value::Array(a) => Ok(a.borrow().clone()),
+        val => Err(wrong_type("Array", val)),
+    }
+}
+
+/// Group the elements of an array into a map of arrays, by the key a function returns for each.
+fn group_by(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let mut groups: IndexMap<Key, Vec<Value>> = IndexMap::new();
+    for val in elements(&vals[0])? {
+        let key = Key::new(vm.call(vals[1].clone(), vec![val.clone()])?)?;
+        groups.entry(key).or_default().push(val);
+    }
+    let groups = groups
+        .into_iter()
+        .map(|(key, vals)| (key, Value::new_array(vals)))
+        .collect();
+    Ok(Value::new_map_from(groups))
+}
+
+/// Split an array in two: the elements a function returns a truthy value for, and the rest.
+fn partition(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let (mut matching, mut rest) = (Vec::new(), Vec::new());
+    for val in elements(&vals[0])? {
+        if vm.call(vals[1].clone(), vec![val.clone()])?.is_truthy() {
+            matching.push(val);
+        } else {
+            rest.push(val);
+        }
+    }
+    Ok(Value::new_array(vec![
+        Value::new_array(matching),
+        Value::new_array(rest),
+    ]))
+}
+
+/// Replace the arrays inside an array with their elements,
+/// as many levels deep as asked to, or only one by default.
+fn flatten(vm: &mut VirtualMachine, vals: &[Value]) -> Result<Value> {
+    let depth = vals
+        .get(1)
+        .map(|val| vm.as_index(val))
+        .transpose()?
+        .unwrap_or(1);
+    let mut flat = Vec::new();
+    flatten_into(&mut flat, elements(&vals[0])?, depth);
+    Ok(Value::new_array(flat))
+}
+
+fn flatten_into(flat: &mut Vec<Value>, vals: Vec<Value>, depth: usize) {
+    for val in vals {
+        match val {
+            Value::Array(a) if depth > 0 => {
+                let inner = a.borrow().clone();
+                flatten_into(flat, inner, depth - 1);
+            }
+            val => flat.push(val),
+        }
+    }
+}
+
+/// Wrap a function value so that the result of each call is cached,
+/// and later calls with the same arguments return it without running the function again.
+fn memoize(vals: &[Value]) -> Result<Value> {
+    let f = vals[0].clone();
+    let arity = fn_arity(&f)?;
+    let cache: RefCell<HashMap<Vec<Key>, Value>> = RefCell::default();
+    let memoized = move |vm: &mut VirtualMachine, args: &[Value]| {
+        let key = args
+            .iter()
+            .cloned()
+            .map(Key::new)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if let Some(result) = cache.borrow().get(&key) {
+            return Ok(result.clone());
+        }
+        let result = vm.call(f.clone(), args.to_vec())?;
+        cache.borrow_mut().insert(key, result.clone());
+        Ok(result)
+    };
+    Ok(Value::NativeFn {
+        f: Rc::new(memoized),
+        arity,
+        name: None,
+    })
+}
+
+/// Chain two functions together, so that `compose(f, g)(x)` is `f(g(x))`.
+fn compose(vals: &[Value]) -> Result<Value> {
+    let (f, g) = (vals[0].clone(), vals[1].clone());
+    fn_arity(&f)?;
+    let arity = fn_arity(&g)?;
+    let composed = move |vm: &mut VirtualMachine, args: &[Value]| {
+        let inner = vm.call(g.clone(), args.to_vec())?;
+        vm.call(f.clone(), vec![inner])
+    };
+    Ok(Value::NativeFn {
+        f: Rc::new(composed),
+        arity,
+        name: None,
+    })
+}
+
+/// Fix the first arguments of a function to the values in an array,
+/// returning a function which only takes the remaining ones.
+fn partial(vals: &[Value]) -> Result<Value> {
+    let f = vals[0].clone();
+    let arity = fn_arity(&f)?;
+    let bound = match &vals[1] {
+        Value::Array(a) => a.borrow().clone(),
+        val => return Err(wrong_type("Array", val)),
+    };
+    let remaining = match arity.after(bound.len()) {
+        Some(remaining) => remaining,
+        None => {
+            return Err(crate::vm::Error::WrongArgCount {
+                expected: arity,
+                found: bound.len().try_into()?,
+            })
+        }
+    };
+    let applied = move |vm: &mut VirtualMachine, args: &[Value]| {
+        let args = bound.iter().chain(args).cloned().collect();
+        vm.call(f.clone(), args)
+    };
+    Ok(Value::NativeFn {
+        f: Rc::new(applied),
+        arity: remaining,
+        name: None,
+    })
+}
+
+/// The number of arguments `val` takes, failing if it isn't a function.
+fn fn_arity(val: &Value) -> Result<Arity> {
+    match val {
+        Value::Function { chunk } => Ok(Arity::from(chunk.arity)),
+        Value::NativeFn { arity, .. } => Ok(*arity),
+        val => Err(wrong_type("Fn", val)),
+    }
+}
+
+/// Define a native taking a fixed number of arguments, or a range of them like `1..=2`.
+fn define(
+    vm: &mut VirtualMachine,
+    name: &str,
+    arity: impl Into<Arity>,
+    f: fn(&[Value]) -> Result<Value>,
+) {
+    vm.define(
+        name.to_owned(),
+        Value::NativeFn {
+            f: Rc::new(move |_, vals| f(vals)),
+            arity: arity.into(),
+            name: Some(Rc::new(name.to_owned())),
+        },
+    );
+}
+
+/// Define a native applying a function of one float to its argument.
+fn define_float(vm: &mut VirtualMachine, name: &str, f: fn(f64) -> f64) {
+    vm.define(
+        name.to_owned(),
+        Value::NativeFn {
+            f: Rc::new(move |_, vals| Ok(Value::Num(f(num_arg(&vals[0])?)))),
+            arity: 1.into(),
+            name: Some(Rc::new(name.to_owned())),
+        },
+    );
+}
+
+/// Define `temp_file` and `temp_dir`, whose paths are deleted once both natives are dropped.
+fn define_temp(vm: &mut VirtualMachine, deterministic: bool) {
+    let temps = Rc::new(TempPaths::new(deterministic));
+    type Create = fn(&TempPaths) -> io::Result<PathBuf>;
+    let natives: [(&str, Create); 2] =
+        [("temp_file", TempPaths::file), ("temp_dir", TempPaths::dir)];
+    for (name, create) in natives {
+        let temps = temps.clone();
+        vm.define(
+            name.to_owned(),
+            Value::NativeFn {
+                f: Rc::new(move |_, _| Ok(path_value(&create(&temps)?))),
+                arity: 0.into(),
+                name: Some(Rc::new(name.to_owned())),
+            },
+        );
+    }
+}
+
+/// Define a frozen map of natives already defined, keyed by their names without the
+/// namespace's own prefix, so that `log.info` is the same function as `log_info`.
+fn define_namespace(vm: &mut VirtualMachine, namespace: &str, names: &[&str]) {
+    let prefix = format!("{}_", namespace);
+    let entries = names
+        .iter()
+        .map(|name| {
+            let native = vm
+                .global(name)
+                .cloned()
+                .expect("Namespaced native isn't defined");
+            let key = name.strip_prefix(&prefix).unwrap_or(name);
+            (
+                Key::new(Value::new_str(key)).expect("Strings are keys"),
+                native,
+            )
+        })
+        .collect();
+    vm.define(namespace.to_owned(), Value::new_map_from(entries).freeze());
+}
+
+/// Define a native which needs access to the VM calling it.
+fn define_with_vm(
+    vm: &mut VirtualMachine,
+    name: &str,
+    arity: impl Into<Arity>,
+    f: fn(&mut VirtualMachine, &[Value]) -> Result<Value>,
+) {
     vm.define(
-        "print".to_owned(),
+        name.to_owned(),
         Value::NativeFn {
-            f: Rc::new(print),
-            arity: 1,
+            f: Rc::new(f),
+            arity: arity.into(),
+            name: Some(Rc::new(name.to_owned())),
         },
     );
 }
+
+pub fn load_libraries(vm: &mut VirtualMachine, config: &Config) {
+    define(vm, "print", 0.., print);
+    define(vm, "write", 0.., write);
+    define(vm, "join", 2, join);
+    define_with_vm(vm, "inspect", 1..=2, inspect);
+    define(vm, "len", 1, len);
+    define(vm, "lines", 1, lines);
+    define(vm, "split_whitespace", 1, split_whitespace);
+    define(vm, "trim", 1, trim);
+    define(vm, "trim_start", 1, trim_start);
+    define(vm, "trim_end", 1, trim_end);
+    define(vm, "strip_prefix", 2, strip_prefix);
+    define(vm, "strip_suffix", 2, strip_suffix);
+    define(vm, "to_utf8_bytes", 1, to_utf8_bytes);
+    define(vm, "from_utf8", 1, from_utf8);
+    define(vm, "from_utf8_lossy", 1, from_utf8_lossy);
+    define(vm, "is_valid_utf8", 1, is_valid_utf8);
+    define(vm, "bigint", 1, bigint);
+    define(vm, "decimal", 1, decimal);
+    define_with_vm(vm, "decimal_round", 3, decimal_round);
+    define(vm, "clamp", 3, clamp);
+    define(vm, "lerp", 3, lerp);
+    define(vm, "sign", 1, sign);
+    define(vm, "trunc", 1, trunc);
+    define_float(vm, "sin", f64::sin);
+    define_float(vm, "cos", f64::cos);
+    define_float(vm, "tan", f64::tan);
+    define_float(vm, "sinh", f64::sinh);
+    define_float(vm, "cosh", f64::cosh);
+    define_float(vm, "tanh", f64::tanh);
+    define_float(vm, "to_radians", f64::to_radians);
+    define_float(vm, "to_degrees", f64::to_degrees);
+    define(vm, "atan2", 2, atan2);
+    define(vm, "hypot", 2, hypot);
+    define(vm, "mean", 1, mean);
+    define(vm, "median", 1, median);
+    define(vm, "stddev", 1, stddev);
+    define(vm, "percentile", 2, percentile);
+    define(vm, "try_num", 1, try_num);
+    define(vm, "try_int", 1, try_int);
+    define(vm, "try_json", 1, try_json);
+    define(vm, "parse", 2, parse);
+    define(vm, "scan_num", 1, scan_num);
+    define(vm, "scan_word", 1, scan_word);
+    define(vm, "push", 2, push);
+    define(vm, "pop", 1, pop);
+    define_with_vm(vm, "range", 1..=3, range);
+    define_with_vm(vm, "slice", 3, slice);
+    define_with_vm(vm, "chunks", 2, chunks);
+    define_with_vm(vm, "windows", 2, windows);
+    define(vm, "reverse", 1, reverse);
+    define(vm, "index_of", 2, index_of);
+    define(vm, "last_index_of", 2, last_index_of);
+    define_with_vm(vm, "find", 2, find);
+    define_with_vm(vm, "find_index", 2, find_index);
+    define(vm, "binary_search", 2, binary_search);
+    define(vm, "sorted_insert", 2, sorted_insert);
+    define(vm, "unique", 1, unique);
+    define(vm, "sort", 1, sort);
+    define_with_vm(vm, "sort_by", 2, sort_by);
+    define(vm, "cmp_ignore_case", 2, cmp_ignore_case);
+    define(vm, "natural_cmp", 2, natural_cmp);
+    define(vm, "map", 0, map);
+    define_with_vm(vm, "get", 2..=3, get);
+    define_with_vm(vm, "set", 3, set);
+    define(vm, "remove", 2, remove);
+    define(vm, "keys", 1, keys);
+    define(vm, "merge", 2, merge);
+    define(vm, "entries", 1, entries);
+    define(vm, "from_entries", 1, from_entries);
+    define(vm, "to_set", 1, to_set);
+    define(vm, "to_array", 1, to_array);
+    define(vm, "add", 2, add);
+    define(vm, "contains", 2, contains);
+    define(vm, "hash", 1, hash);
+    define(vm, "freeze", 1, freeze);
+    define(vm, "is_frozen", 1, is_frozen);
+    define(vm, "weak", 1, weak);
+    define(vm, "deref", 1, deref);
+    define(vm, "ok", 1, ok);
+    define(vm, "err", 1, err);
+    define(vm, "is_null", 1, is_null);
+    define(vm, "is_num", 1, is_num);
+    define(vm, "is_str", 1, is_str);
+    define(vm, "is_bool", 1, is_bool);
+    define(vm, "is_array", 1, is_array);
+    define(vm, "is_map", 1, is_map);
+    define(vm, "is_fn", 1, is_fn);
+    define(vm, "is_ok", 1, is_ok);
+    define(vm, "is_err", 1, is_err);
+    define(vm, "unwrap_or", 2, unwrap_or);
+    define_with_vm(vm, "panic", 1, panic);
+    define_with_vm(vm, "group_by", 2, group_by);
+    define_with_vm(vm, "partition", 2, partition);
+    define_with_vm(vm, "flatten", 1..=2, flatten);
+    define(vm, "store_open", 1, store_open);
+    define(vm, "store_get", 2, store_get);
+    define(vm, "store_set", 3, store_set);
+    define(vm, "store_delete", 2, store_delete);
+    define(vm, "store_keys", 1, store_keys);
+    define(vm, "glob", 1, glob);
+    define_with_vm(vm, "walk_dir", 2, walk_dir);
+    define_temp(vm, config.deterministic);
+    define_with_vm(vm, "on_signal", 2, on_signal);
+    define(vm, "readline", 0..=1, readline);
+    define(vm, "readline_history", 1, readline_history);
+    define(vm, "flags_new", 0..=1, flags_new);
+    define(vm, "flags_add", 3..=4, flags_add);
+    define(vm, "flags_parse", 2, flags_parse);
+    define(vm, "flags_help", 1, flags_help);
+    define(vm, "style", 2, style);
+    define(vm, "clear_screen", 0, clear_screen);
+    define_with_vm(vm, "move_cursor", 2, move_cursor);
+    define(vm, "term_width", 0, term_width);
+    define(vm, "is_terminal", 0, is_terminal);
+    define(vm, "log_debug", 1, log_debug);
+    define(vm, "log_info", 1, log_info);
+    define(vm, "log_warn", 1, log_warn);
+    define(vm, "log_error", 1, log_error);
+    define(vm, "log_set_level", 1, log_set_level);
+    define(vm, "log_to_file", 1, log_to_file);
+    define(vm, "sb_new", 0, sb_new);
+    define(vm, "sb_push", 2, sb_push);
+    define(vm, "sb_build", 1, sb_build);
+    define(vm, "memoize", 1, memoize);
+    define(vm, "compose", 2, compose);
+    define(vm, "partial", 2, partial);
+    let math = [
+        "bigint",
+        "decimal",
+        "decimal_round",
+        "clamp",
+        "lerp",
+        "sign",
+        "trunc",
+        "sin",
+        "cos",
+        "tan",
+        "sinh",
+        "cosh",
+        "tanh",
+        "to_radians",
+        "to_degrees",
+        "atan2",
+        "hypot",
+        "mean",
+        "median",
+        "stddev",
+        "percentile",
+    ];
+    define_namespace(vm, "math", &math);
+    let io = [
+        "print",
+        "write",
+        "readline",
+        "readline_history",
+        "glob",
+        "walk_dir",
+        "temp_file",
+        "temp_dir",
+    ];
+    define_namespace(vm, "io", &io);
+    let term = [
+        "style",
+        "clear_screen",
+        "move_cursor",
+        "term_width",
+        "is_terminal",
+    ];
+    define_namespace(vm, "term", &term);
+    let log = [
+        "log_debug",
+        "log_info",
+        "log_warn",
+        "log_error",
+        "log_set_level",
+        "log_to_file",
+    ];
+    define_namespace(vm, "log", &log);
+    let store = [
+        "store_open",
+        "store_get",
+        "store_set",
+        "store_delete",
+        "store_keys",
+    ];
+    define_namespace(vm, "store", &store);
+    let flags = ["flags_new", "flags_add", "flags_parse", "flags_help"];
+    define_namespace(vm, "flags", &flags);
+    let sb = ["sb_new", "sb_push", "sb_build"];
+    define_namespace(vm, "sb", &sb);
+}
@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write as _};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How important a log message is. Messages below the logger's level are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.to_ascii_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Debug => f.pad("DEBUG"),
+            Level::Info => f.pad("INFO"),
+            Level::Warn => f.pad("WARN"),
+            Level::Error => f.pad("ERROR"),
+        }
+    }
+}
+
+struct Logger {
+    level: Level,
+    /// Where messages go instead of standard error, if anywhere.
+    file: Option<File>,
+    /// The number of messages logged so far, when they're timestamped by counting them
+    /// instead of reading the clock.
+    logical_clock: Option<u64>,
+}
+
+thread_local! {
+    static LOGGER: RefCell<Logger> = RefCell::new(Logger {
+        level: env::var("OXIDE_LOG")
+            .ok()
+            .and_then(|name| Level::parse(&name))
+            .unwrap_or(Level::Info),
+        file: None,
+        logical_clock: None,
+    });
+}
+
+pub fn set_level(level: Level) {
+    LOGGER.with(|logger| logger.borrow_mut().level = level);
+}
+
+/// Timestamp messages as if a millisecond passed between each one, starting at the epoch,
+/// so that runs of the same script log exactly the same thing.
+pub fn use_logical_clock() {
+    LOGGER.with(|logger| logger.borrow_mut().logical_clock = Some(0));
+}
+
+/// Append messages to the file at `path` from now on, or write them to standard error again.
+pub fn set_file(path: Option<&str>) -> io::Result<()> {
+    let file = match path {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    LOGGER.with(|logger| logger.borrow_mut().file = file);
+    Ok(())
+}
+
+/// Write `message` on its own line, preceded by the current time and its level.
+pub fn log(level: Level, message: &str) -> io::Result<()> {
+    LOGGER.with(|logger| {
+        let mut logger = logger.borrow_mut();
+        if level < logger.level {
+            return Ok(());
+        }
+        let now = match &mut logger.logical_clock {
+            Some(count) => {
+                *count += 1;
+                Duration::from_millis(*count)
+            }
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        };
+        let line = format!("{} {:<5} {}\n", timestamp(now), level, message);
+        match &mut logger.file {
+            Some(file) => file.write_all(line.as_bytes()),
+            None => io::stderr().write_all(line.as_bytes()),
+        }
+    })
+}
+
+/// A UTC time given since the epoch in RFC 3339 format, to the millisecond.
+fn timestamp(now: Duration) -> String {
+    let secs = now.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Convert days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        now.subsec_millis()
+    )
+}
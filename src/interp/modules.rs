@@ -0,0 +1,161 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::compile::Compiler;
+use crate::diag::Policy;
+use crate::scan::TokenStream;
+use crate::vm::{Arity, Result, Value, ValueError, VirtualMachine};
+use crate::xref;
+
+use super::print_error_in;
+
+/// The extension given to module names which don't have one.
+const EXTENSION: &str = "o2";
+
+/// The file name of the module called `name`.
+fn module_file(name: &str) -> PathBuf {
+    let mut file = PathBuf::from(name);
+    if file.extension().is_none() {
+        file.set_extension(EXTENSION);
+    }
+    file
+}
+
+/// Loads the modules a script imports, running each of them only once.
+pub struct Importer {
+    /// Directories searched for modules, in order.
+    paths: Vec<PathBuf>,
+    policy: Policy,
+    /// The source of modules which don't need to be looked up on disk, by file name.
+    bundled: HashMap<PathBuf, String>,
+    /// The value of each module imported so far, or nothing while it's still running.
+    modules: RefCell<HashMap<PathBuf, Option<Value>>>,
+}
+
+impl Importer {
+    pub fn new(paths: Vec<PathBuf>, policy: Policy) -> Self {
+        Importer {
+            paths,
+            policy,
+            bundled: HashMap::new(),
+            modules: RefCell::default(),
+        }
+    }
+
+    /// Prefer the given sources to the files on disk when importing modules.
+    pub fn with_bundled(mut self, bundled: HashMap<PathBuf, String>) -> Self {
+        self.bundled = bundled;
+        self
+    }
+
+    /// Find the module called `name`, returning the file it's in, or its file name if it's bundled.
+    fn resolve(&self, name: &str) -> Option<PathBuf> {
+        let file = module_file(name);
+        if self.bundled.contains_key(&file) {
+            return Some(file);
+        }
+        self.paths
+            .iter()
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+            .map(|path| path.canonicalize().unwrap_or(path))
+    }
+
+    fn source(&self, path: &Path) -> Result<String> {
+        match self.bundled.get(path) {
+            Some(source) => Ok(source.clone()),
+            None => Ok(fs::read_to_string(path)?),
+        }
+    }
+
+    fn import(&self, vm: &mut VirtualMachine, name: &str) -> Result<Value> {
+        let path = self.resolve(name).ok_or_else(|| not_found(name))?;
+        match self.modules.borrow().get(&path) {
+            Some(Some(val)) => return Ok(val.clone()),
+            Some(None) => {
+                let msg = format!("Module '{}' imports itself", name);
+                return Err(ValueError::InvalidArgument(msg).into());
+            }
+            None => (),
+        }
+        let module = self.compile(name, &path, &self.source(&path)?)?;
+        self.modules.borrow_mut().insert(path.clone(), None);
+        let result = vm.call(module, Vec::new());
+        match &result {
+            Ok(val) => self.modules.borrow_mut().insert(path, Some(val.clone())),
+            Err(_) => self.modules.borrow_mut().remove(&path),
+        };
+        result
+    }
+
+    /// Compile the module at `path` into a function running it,
+    /// printing any warnings and errors found along the way.
+    fn compile(&self, name: &str, path: &Path, text: &str) -> Result<Value> {
+        let mut compiler = Compiler::new();
+        let mut stream = TokenStream::new(text).peekable();
+        let module = compiler.module(name.to_owned(), &mut stream);
+        let denied = self.policy.report(&compiler.diagnostics(), Some(text));
+        match module {
+            Ok(module) if denied == 0 => Ok(module),
+            result => {
+                if let Err(err) = result {
+                    print_error_in(&err.into(), text, &path.display().to_string());
+                }
+                let msg = format!("Could not compile module '{}'", name);
+                Err(ValueError::InvalidArgument(msg).into())
+            }
+        }
+    }
+
+    /// Find the source of every module `module` imports, directly or through other modules,
+    /// by file name. Only imports of names written out as strings can be found.
+    pub fn collect(&self, module: &Value) -> Result<HashMap<PathBuf, String>> {
+        let mut sources = HashMap::new();
+        let mut pending = module_imports(module);
+        while let Some(name) = pending.pop() {
+            let file = module_file(&name);
+            if sources.contains_key(&file) {
+                continue;
+            }
+            let path = self.resolve(&name).ok_or_else(|| not_found(&name))?;
+            let text = self.source(&path)?;
+            pending.extend(module_imports(&self.compile(&name, &path, &text)?));
+            sources.insert(file, text);
+        }
+        Ok(sources)
+    }
+
+    /// Define the `import` native, which imports modules through this importer.
+    pub fn define(self, vm: &mut VirtualMachine) {
+        let import = move |vm: &mut VirtualMachine, args: &[Value]| match &args[0] {
+            Value::Str(name) => self.import(vm, name),
+            val => Err(ValueError::WrongType {
+                expected: "Str",
+                found: val.clone(),
+            }
+            .into()),
+        };
+        vm.define(
+            "import".to_owned(),
+            Value::NativeFn {
+                f: Rc::new(import),
+                arity: Arity::from(1),
+                name: Some(Rc::new("import".to_owned())),
+            },
+        );
+    }
+}
+
+fn module_imports(module: &Value) -> Vec<String> {
+    match module {
+        Value::Function { chunk } => xref::imports(&chunk.code),
+        _ => Vec::new(),
+    }
+}
+
+fn not_found(name: &str) -> crate::vm::Error {
+    ValueError::InvalidArgument(format!("Could not find module '{}'", name)).into()
+}
@@ -0,0 +1,32 @@
+use std::cell::RefCell;
+use std::io::{self, Write as _};
+
+thread_local! {
+    /// What `print` and `write` output since it started being captured, if it is.
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Write `text` to standard output, or add it to the captured output if it's being captured.
+pub fn write(text: &str) -> io::Result<()> {
+    let captured = CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(buf) => {
+            buf.push_str(text);
+            true
+        }
+        None => false,
+    });
+    if !captured {
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Run `f`, returning what it printed through `write` instead of printing it.
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let outer = CAPTURED.with(|captured| captured.replace(Some(String::new())));
+    let result = f();
+    let text = CAPTURED.with(|captured| captured.replace(outer));
+    (result, text.unwrap_or_default())
+}
@@ -0,0 +1,189 @@
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+
+use crate::vm::{Key, Value};
+
+/// What a placeholder of a pattern matches.
+#[derive(Clone, Copy)]
+enum Kind {
+    /// Any text, up to where the literal text following the placeholder starts.
+    Text,
+    /// A run of characters other than whitespace.
+    Word,
+    /// A number, with an optional sign, fraction and exponent.
+    Num,
+    /// A whole number, with an optional sign.
+    Int,
+}
+
+enum Part<'a> {
+    Literal(String),
+    Placeholder(&'a str, Kind),
+}
+
+/// Split a pattern into its literal text and its placeholders, written as `{name}` or
+/// `{name:kind}`. Braces are written as `{{` and `}}` in the literal text.
+fn compile(pattern: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = pattern;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '{' if rest.starts_with('{') => {
+                rest = &rest[1..];
+                literal.push('{');
+            }
+            '}' if rest.starts_with('}') => {
+                rest = &rest[1..];
+                literal.push('}');
+            }
+            '{' => {
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| format!("Unclosed '{{' in pattern '{}'", pattern))?;
+                let (name, kind) = match rest[..end].split_once(':') {
+                    Some((name, kind)) => (name, kind),
+                    None => (&rest[..end], "text"),
+                };
+                let kind = match kind {
+                    "text" => Kind::Text,
+                    "word" => Kind::Word,
+                    "num" => Kind::Num,
+                    "int" => Kind::Int,
+                    kind => {
+                        return Err(format!(
+                            "Unknown placeholder kind '{}', expected text, word, num or int",
+                            kind
+                        ))
+                    }
+                };
+                if name.is_empty() {
+                    return Err(format!(
+                        "Placeholder without a name in pattern '{}'",
+                        pattern
+                    ));
+                }
+                if let Some(Part::Placeholder(prev, Kind::Text)) = parts.last() {
+                    if literal.is_empty() {
+                        return Err(format!(
+                            "Placeholder '{}' has to be followed by text, or end the pattern",
+                            prev
+                        ));
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Placeholder(name, kind));
+                rest = &rest[end + 1..];
+            }
+            '}' => return Err(format!("Unmatched '}}' in pattern '{}'", pattern)),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// The length of the run of ASCII digits at the start of `s`.
+fn digits(s: &str) -> usize {
+    s.bytes().take_while(u8::is_ascii_digit).count()
+}
+
+/// The length of the sign at the start of `s`, if there is one.
+fn sign(s: &str) -> usize {
+    match s.as_bytes().first() {
+        Some(b'+') | Some(b'-') => 1,
+        _ => 0,
+    }
+}
+
+/// A whole number from its digits, which is a big integer if it's too big for an int.
+fn int_value(digits: &str) -> Option<Value> {
+    match digits.parse::<i64>() {
+        Ok(n) => Some(Value::Int(n)),
+        Err(_) => digits.parse::<BigInt>().ok().map(Value::from),
+    }
+}
+
+/// The whole number at the start of `s`, along with its length.
+pub fn scan_int(s: &str) -> Option<(Value, usize)> {
+    let start = sign(s);
+    let len = start + digits(&s[start..]);
+    if len == start {
+        return None;
+    }
+    Some((int_value(&s[..len])?, len))
+}
+
+/// The number at the start of `s`, along with its length.
+/// It's an int unless it has a fraction or an exponent.
+pub fn scan_num(s: &str) -> Option<(Value, usize)> {
+    let (int, int_len) = scan_int(s)?;
+    let mut len = int_len;
+    let fraction = s[len..].strip_prefix('.').map_or(0, digits);
+    if fraction > 0 {
+        len += 1 + fraction;
+    }
+    if let Some(exp) = s[len..].strip_prefix(['e', 'E']) {
+        let exp_digits = digits(&exp[sign(exp)..]);
+        if exp_digits > 0 {
+            len += 1 + sign(exp) + exp_digits;
+        }
+    }
+    if len == int_len {
+        return Some((int, len));
+    }
+    let x = s[..len].parse::<f64>().ok().filter(|x| x.is_finite())?;
+    Some((Value::Num(x), len))
+}
+
+/// The length of the word at the start of `s`, which runs up to the first whitespace.
+pub fn scan_word(s: &str) -> Option<usize> {
+    let len = s.find(char::is_whitespace).unwrap_or(s.len());
+    Some(len).filter(|&len| len > 0)
+}
+
+/// Match all of `s` against a pattern of literal text and placeholders, returning what
+/// each placeholder matched by name, or None if `s` doesn't match. Placeholders match
+/// as much as they can, without going back to try matching less.
+pub fn parse(s: &str, pattern: &str) -> Result<Option<IndexMap<Key, Value>>, String> {
+    let parts = compile(pattern)?;
+    Ok(matches(s, &parts))
+}
+
+fn matches(s: &str, parts: &[Part]) -> Option<IndexMap<Key, Value>> {
+    let mut captures = IndexMap::new();
+    let mut rest = s;
+    for (i, part) in parts.iter().enumerate() {
+        let (name, kind) = match part {
+            Part::Literal(text) => {
+                rest = rest.strip_prefix(text.as_str())?;
+                continue;
+            }
+            Part::Placeholder(name, kind) => (name, kind),
+        };
+        let (val, len) = match kind {
+            Kind::Text => {
+                let len = match parts.get(i + 1) {
+                    Some(Part::Literal(text)) => rest.find(text.as_str())?,
+                    _ => rest.len(),
+                };
+                (Value::new_str(&rest[..len]), len)
+            }
+            Kind::Word => {
+                let len = scan_word(rest)?;
+                (Value::new_str(&rest[..len]), len)
+            }
+            Kind::Num => scan_num(rest)?,
+            Kind::Int => scan_int(rest)?,
+        };
+        let key = Key::new(Value::new_str(*name)).expect("Strings are keys");
+        captures.insert(key, val);
+        rest = &rest[len..];
+    }
+    Some(captures).filter(|_| rest.is_empty())
+}
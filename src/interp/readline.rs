@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::io;
+use std::path::PathBuf;
+
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+/// The line editor shared by every call to `readline`, so they share their history.
+struct LineEditor {
+    editor: Editor<()>,
+    /// Where the history is kept between runs, if anywhere.
+    history_file: Option<PathBuf>,
+}
+
+thread_local! {
+    static EDITOR: RefCell<Option<LineEditor>> = const { RefCell::new(None) };
+}
+
+fn with_editor<T>(f: impl FnOnce(&mut LineEditor) -> T) -> T {
+    EDITOR.with(|editor| {
+        let mut editor = editor.borrow_mut();
+        let editor = editor.get_or_insert_with(|| LineEditor {
+            editor: Editor::new(),
+            history_file: None,
+        });
+        f(editor)
+    })
+}
+
+/// Read a line from the terminal after showing `prompt`, with line editing and history.
+/// Returns None once the input ends.
+pub fn read(prompt: &str) -> Result<Option<String>, ReadlineError> {
+    with_editor(|rl| match rl.editor.readline(prompt) {
+        Ok(line) => {
+            if !line.trim().is_empty() {
+                rl.editor.add_history_entry(line.as_str());
+                if let Some(path) = &rl.history_file {
+                    rl.editor.save_history(path)?;
+                }
+            }
+            Ok(Some(line))
+        }
+        Err(ReadlineError::Eof) => Ok(None),
+        Err(err) => Err(err),
+    })
+}
+
+/// Load the history from the file at `path`, if it exists, and save it there after every line.
+pub fn set_history_file(path: PathBuf) -> io::Result<()> {
+    with_editor(|rl| {
+        match rl.editor.load_history(&path) {
+            Ok(()) => {}
+            Err(ReadlineError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(into_io_error(err)),
+        }
+        rl.history_file = Some(path);
+        Ok(())
+    })
+}
+
+pub fn into_io_error(err: ReadlineError) -> io::Error {
+    match err {
+        ReadlineError::Io(err) => err,
+        err => io::Error::other(err.to_string()),
+    }
+}
@@ -0,0 +1,241 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use super::session::{SavePoint, Session};
+use super::{check, print_error, print_error_in, read_source, signals, Config, Error, Result};
+use crate::compile::{self, Compiler};
+use crate::scan::TokenStream;
+use crate::vm::Value;
+use crate::xref;
+
+/// The prompt shown when the REPL is waiting for a new line of code.
+pub(super) const PROMPT: &str = ">> ";
+/// The prompt shown while the code entered so far is incomplete.
+pub(super) const CONTINUATION_PROMPT: &str = ".. ";
+
+/// The script run when the REPL starts, relative to the user's home directory.
+const STARTUP_SCRIPT: &str = ".oxiderc";
+/// How many pieces of code `:undo` can take back.
+const UNDO_LIMIT: usize = 100;
+
+pub fn repl(config: &Config) {
+    let mut rl = Editor::<()>::new();
+    let startup_script = env::var_os("HOME").map(|home| Path::new(&home).join(STARTUP_SCRIPT));
+    let mut repl = Repl::new(config, startup_script);
+    // Lines entered so far, as long as they don't form complete code.
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() {
+            repl.prompt("prompt", PROMPT)
+        } else {
+            repl.prompt("continuation_prompt", CONTINUATION_PROMPT)
+        };
+        match rl.readline(&prompt) {
+            Ok(line) => {
+                if input.is_empty() && line.trim_start().starts_with(':') {
+                    rl.add_history_entry(line.as_str());
+                    repl.command(line.trim());
+                    continue;
+                }
+                input.push_str(&line);
+                input.push('\n');
+                let result = repl.run(&input);
+                match &result {
+                    Err(Error::Compilation(err)) if err.is_incomplete() => continue,
+                    // Code which couldn't even be scanned has to be typed again anyway,
+                    // so it's left out of the history rather than being brought back up.
+                    Err(Error::Compilation(compile::Error::Scan(_))) => (),
+                    _ => {
+                        for line in input.lines() {
+                            rl.add_history_entry(line);
+                        }
+                    }
+                }
+                match result {
+                    Ok(val) => println!("{}", val),
+                    Err(err) => print_error(&err, &input),
+                }
+                input.clear();
+            }
+            // Interrupting an incomplete piece of code only throws it away.
+            Err(ReadlineError::Interrupted) if !input.is_empty() => input.clear(),
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
+                break;
+            }
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// The state of the REPL, kept between one piece of code and the next.
+pub(super) struct Repl<'a> {
+    session: Session,
+    config: &'a Config,
+    startup_script: Option<PathBuf>,
+    /// Every piece of code which ran without errors, in order.
+    history: Vec<String>,
+    /// The state of the session before each of the latest pieces of code was run.
+    undo: Vec<UndoPoint>,
+}
+
+struct UndoPoint {
+    point: SavePoint,
+    history: usize,
+}
+
+impl<'a> Repl<'a> {
+    pub(super) fn new(config: &'a Config, startup_script: Option<PathBuf>) -> Self {
+        let mut repl = Repl {
+            session: Session::new(config),
+            config,
+            startup_script,
+            history: Vec::new(),
+            undo: Vec::new(),
+        };
+        repl.reset();
+        repl
+    }
+
+    /// Start over with only the standard library and what the startup script defines.
+    fn reset(&mut self) {
+        self.session = Session::new(self.config);
+        self.session
+            .vm_mut()
+            .set_interrupt(signals::catch_interrupts());
+        self.history.clear();
+        if let Some(path) = self.startup_script.clone() {
+            self.run_startup_script(&path);
+        }
+        self.undo.clear();
+    }
+
+    /// Replace the functions declared by the script at `path` with their latest versions,
+    /// without running anything else in it. Nothing changes unless the whole script compiles.
+    fn reload(&mut self, path: &str) {
+        let text = match read_source(path) {
+            Ok(text) => text,
+            Err(err) => return eprintln!("{}: {}", path, err),
+        };
+        let mut compiler = Compiler::new();
+        let mut stream = TokenStream::new(&text).peekable();
+        let compiled = compiler
+            .program(&mut stream)
+            .map_err(Error::from)
+            .and_then(|()| check(&mut compiler, &self.config.policy, Some(&text)));
+        if let Err(err) = compiled {
+            return print_error_in(&err, &text, path);
+        }
+        let functions = xref::functions(&compiler.instructions());
+        if functions.is_empty() {
+            return eprintln!("{} doesn't declare any functions", path);
+        }
+        let names: Vec<_> = functions.iter().map(|(name, _)| name.clone()).collect();
+        self.remember(self.session.save());
+        self.session.vm_mut().redefine(functions);
+        println!("Reloaded {}", names.join(", "));
+    }
+
+    /// Go back to the state the session was in before the last piece of code was run.
+    fn undo(&mut self) {
+        match self.undo.pop() {
+            Some(undo) => {
+                self.session.restore(undo.point);
+                self.history.truncate(undo.history);
+            }
+            None => eprintln!("Nothing to undo"),
+        }
+    }
+
+    /// The prompt stored in the global `name`, which scripts can set to customize it.
+    fn prompt(&self, name: &str, default: &str) -> String {
+        match self.session.vm().global(name) {
+            None | Some(Value::Null) => default.to_owned(),
+            Some(val) => val.to_string(),
+        }
+    }
+
+    /// Run the script at `path` as if it was typed into the REPL, if it exists.
+    fn run_startup_script(&mut self, path: &Path) {
+        let text = match read_source(path) {
+            Ok(text) => text,
+            Err(Error::IO(err)) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                return;
+            }
+        };
+        if let Err(err) = self.execute(&text) {
+            print_error_in(&err, &text, &path.display().to_string());
+        }
+    }
+
+    /// Run a REPL command, written as a colon followed by its name and argument.
+    fn command(&mut self, line: &str) {
+        let (name, arg) = match line.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (line, ""),
+        };
+        match (name, arg) {
+            (":save", path) if !path.is_empty() => {
+                if let Err(err) = fs::write(path, self.history.concat()) {
+                    eprintln!("{}: {}", path, err);
+                }
+            }
+            (":open", path) if !path.is_empty() => match read_source(path) {
+                Ok(text) => match self.run(&text) {
+                    Ok(val) => println!("{}", val),
+                    Err(err) => print_error(&err, &text),
+                },
+                Err(err) => eprintln!("{}: {}", path, err),
+            },
+            (":reload", path) if !path.is_empty() => self.reload(path),
+            (":reset", "") => self.reset(),
+            (":undo", "") => self.undo(),
+            (":reset", _) | (":undo", _) => eprintln!("Usage: {}", name),
+            (":save", _) | (":open", _) | (":reload", _) => eprintln!("Usage: {} <path>", name),
+            _ => eprintln!("Unknown command '{}'", name),
+        }
+    }
+
+    /// Run a piece of code, and remember it if it runs without errors.
+    pub(super) fn run(&mut self, text: &str) -> Result<Value> {
+        let val = self.execute(text)?;
+        let mut entry = text.to_owned();
+        if !entry.ends_with('\n') {
+            entry.push('\n');
+        }
+        self.history.push(entry);
+        Ok(val)
+    }
+
+    /// Run a piece of code, returning the value of its last declaration,
+    /// and make it possible to undo it if it runs without errors.
+    fn execute(&mut self, text: &str) -> Result<Value> {
+        let point = self.session.save();
+        // A Ctrl-C pressed while typing the code isn't meant for it.
+        signals::catch_interrupts().store(false, Ordering::Relaxed);
+        let val = self.session.eval(text)?;
+        self.remember(point);
+        Ok(val)
+    }
+
+    /// Make it possible to undo back to the state saved in `point`.
+    fn remember(&mut self, point: SavePoint) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(UndoPoint {
+            point,
+            history: self.history.len(),
+        });
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use super::{check, Config, Error, Result};
+use crate::compile::{Checkpoint, Compiler};
+use crate::diag::Policy;
+use crate::scan::TokenStream;
+use crate::vm::{Chunk, Snapshot, Value, VirtualMachine};
+
+/// A compiler and VM kept alive between pieces of code, so that each one
+/// sees the variables and functions declared by the ones run before it.
+/// This is what the REPL is built on, and what a host can use to embed one.
+pub struct Session {
+    compiler: Compiler,
+    vm: VirtualMachine,
+    policy: Policy,
+}
+
+/// The state of a session at some point, which it can be taken back to.
+pub struct SavePoint {
+    checkpoint: Checkpoint,
+    snapshot: Snapshot,
+}
+
+impl Session {
+    /// Start a session with the standard library loaded,
+    /// importing modules from the current directory.
+    pub fn new(config: &Config) -> Self {
+        Session {
+            compiler: Compiler::new(),
+            vm: config.vm(Chunk::default(), Path::new("."), Vec::new()),
+            policy: config.policy.clone(),
+        }
+    }
+
+    /// Run a piece of code, returning the value of its last declaration.
+    /// Nothing is run unless all of the code compiles, and if running it fails,
+    /// the variables it declared are forgotten again.
+    pub fn eval(&mut self, src: &str) -> Result<Value> {
+        let checkpoint = self.compiler.checkpoint();
+        let mut stream = TokenStream::new(src).peekable();
+        let compiled = self
+            .compiler
+            .program(&mut stream)
+            .map_err(Error::from)
+            .and_then(|()| check(&mut self.compiler, &self.policy, Some(src)));
+        if let Err(err) = compiled {
+            self.compiler.rollback(checkpoint);
+            return Err(err);
+        }
+        let chunk = self.compiler.instructions();
+        if chunk.is_empty() {
+            return Ok(Value::Null);
+        }
+        self.vm.change_chunk(Chunk::script(chunk));
+        let depth = self.vm.stack_depth();
+        match self.vm.run().and_then(|()| self.vm.pop()) {
+            Ok(val) => Ok(val),
+            Err(err) => {
+                self.vm.unwind(depth);
+                self.compiler.rollback(checkpoint);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Save the current state of the session, to go back to it later with `restore`.
+    pub fn save(&self) -> SavePoint {
+        SavePoint {
+            checkpoint: self.compiler.checkpoint(),
+            snapshot: self.vm.snapshot(),
+        }
+    }
+
+    /// Go back to the state the session was in when `point` was saved.
+    pub fn restore(&mut self, point: SavePoint) {
+        self.compiler.rollback(point.checkpoint);
+        self.vm.restore(&point.snapshot);
+    }
+
+    pub fn vm(&self) -> &VirtualMachine {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VirtualMachine {
+        &mut self.vm
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The signals scripts can handle, by the names they're given in `on_signal`.
+#[cfg(unix)]
+const SIGNALS: [(&str, libc::c_int); 5] = [
+    ("INT", libc::SIGINT),
+    ("TERM", libc::SIGTERM),
+    ("HUP", libc::SIGHUP),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+];
+
+/// Whether each of `SIGNALS` was received since its handler last ran.
+#[cfg(unix)]
+static RECEIVED: [AtomicBool; SIGNALS.len()] = [const { AtomicBool::new(false) }; SIGNALS.len()];
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if let Some(i) = SIGNALS.iter().position(|&(_, s)| s == signal) {
+        RECEIVED[i].store(true, Ordering::Relaxed);
+    }
+}
+
+/// Stop Ctrl-C from killing the process, returning a flag which is set whenever it's pressed.
+pub fn catch_interrupts() -> &'static AtomicBool {
+    #[cfg(unix)]
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    &INTERRUPTED
+}
+
+/// Start catching the signal called `name` (like "INT" or "SIGTERM"),
+/// returning a flag which is set whenever it's received,
+/// or None if there's no such signal, or signals aren't supported.
+#[cfg(unix)]
+pub fn catch(name: &str) -> Option<&'static AtomicBool> {
+    let name = name.strip_prefix("SIG").unwrap_or(name);
+    let i = SIGNALS.iter().position(|&(n, _)| n == name)?;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(
+            SIGNALS[i].1,
+            on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    Some(&RECEIVED[i])
+}
+
+#[cfg(not(unix))]
+pub fn catch(_name: &str) -> Option<&'static AtomicBool> {
+    None
+}
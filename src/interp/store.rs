@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::vm::{json, Result, Value, ValueError};
+
+/// A persistent map from strings to values, saved as a JSON object
+/// to its file after every change.
+pub struct Store {
+    path: PathBuf,
+    entries: RefCell<BTreeMap<String, Value>>,
+}
+
+impl Store {
+    /// Open the store saved at `path`, which starts out empty if the file doesn't exist yet.
+    pub fn open(path: PathBuf) -> Result<Store> {
+        let mut entries = BTreeMap::new();
+        match fs::read_to_string(&path) {
+            Ok(text) => match json::decode(&text).map_err(invalid)? {
+                Value::Map(m) => {
+                    for (key, val) in m.borrow().iter() {
+                        entries.insert(key.value().to_string(), val.clone());
+                    }
+                }
+                _ => {
+                    let msg = format!("{} doesn't contain a JSON object", path.display());
+                    return Err(ValueError::InvalidArgument(msg).into());
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+        Ok(Store {
+            path,
+            entries: RefCell::new(entries),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.entries.borrow().get(key).cloned()
+    }
+
+    pub fn set(&self, key: String, val: Value) -> Result<()> {
+        // Make sure the value can be saved before changing anything.
+        json::encode(&val).map_err(invalid)?;
+        self.entries.borrow_mut().insert(key, val);
+        self.save()
+    }
+
+    pub fn delete(&self, key: &str) -> Result<bool> {
+        let removed = self.entries.borrow_mut().remove(key).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.entries.borrow().keys().cloned().collect()
+    }
+
+    /// Write the whole store to a temporary file, and then move it over the old one,
+    /// so that a crash halfway through never leaves a corrupted store behind.
+    fn save(&self) -> Result<()> {
+        let mut text = String::from("{\n");
+        for (i, (key, val)) in self.entries.borrow().iter().enumerate() {
+            if i > 0 {
+                text.push_str(",\n");
+            }
+            let key = json::encode(&Value::new_str(key.as_str())).map_err(invalid)?;
+            let val = json::encode(val).map_err(invalid)?;
+            text.push_str(&format!("  {}: {}", key, val));
+        }
+        text.push_str(if text.len() > 2 { "\n}\n" } else { "}\n" });
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn invalid(err: json::Error) -> crate::vm::Error {
+    ValueError::InvalidArgument(err.to_string()).into()
+}
@@ -0,0 +1,82 @@
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+/// Temporary files and directories, which are deleted along with it.
+/// The natives creating them hold on to it, so that happens when the VM is dropped.
+pub struct TempPaths {
+    created: RefCell<Vec<PathBuf>>,
+    counter: Cell<u32>,
+    /// The ID of this process, which names include to keep clear of other processes,
+    /// unless they should be the same on every run.
+    process: Option<u32>,
+}
+
+impl TempPaths {
+    /// Paths are named after the process creating them, or only numbered in order
+    /// if `deterministic` is set.
+    pub fn new(deterministic: bool) -> Self {
+        TempPaths {
+            created: RefCell::new(Vec::new()),
+            counter: Cell::new(0),
+            process: if deterministic {
+                None
+            } else {
+                Some(process::id())
+            },
+        }
+    }
+
+    /// Create an empty file in the system's temporary directory, returning its path.
+    pub fn file(&self) -> io::Result<PathBuf> {
+        self.create(|path| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map(drop)
+        })
+    }
+
+    /// Create an empty directory in the system's temporary directory, returning its path.
+    pub fn dir(&self) -> io::Result<PathBuf> {
+        self.create(|path| fs::create_dir(path))
+    }
+
+    /// Create a new path with `create`, trying other names while it's already taken.
+    fn create(&self, create: impl Fn(&PathBuf) -> io::Result<()>) -> io::Result<PathBuf> {
+        loop {
+            let n = self.counter.get();
+            self.counter.set(n.wrapping_add(1));
+            let name = match self.process {
+                Some(id) => format!("oxide-{}-{}", id, n),
+                None => format!("oxide-{}", n),
+            };
+            let path = env::temp_dir().join(name);
+            match create(&path) {
+                Ok(()) => {
+                    self.created.borrow_mut().push(path.clone());
+                    return Ok(path);
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for TempPaths {
+    fn drop(&mut self) {
+        for path in self.created.get_mut().drain(..) {
+            // Scripts may have removed or replaced them already, so errors are fine.
+            if path.is_dir() {
+                let _ = fs::remove_dir_all(&path);
+            } else {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
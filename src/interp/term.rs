@@ -0,0 +1,78 @@
+use std::env;
+use std::io::{self, IsTerminal as _, Write as _};
+
+/// Whether escape sequences should be written to standard output. They aren't when the
+/// output is piped somewhere else, or when the user asked for no colors through `NO_COLOR`.
+pub fn enabled() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
+
+/// The SGR parameter selecting the style called `name`.
+fn style_code(name: &str) -> Option<u8> {
+    const COLORS: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let code = match name {
+        "bold" => 1,
+        "dim" => 2,
+        "italic" => 3,
+        "underline" => 4,
+        "reverse" => 7,
+        _ => {
+            let (base, color) = match name.strip_prefix("on_") {
+                Some(color) => (40, color),
+                None => (30, name),
+            };
+            base + COLORS.iter().position(|c| *c == color)? as u8
+        }
+    };
+    Some(code)
+}
+
+/// Wrap `text` in the escape sequences for the space-separated `styles`,
+/// failing with the name of the first style which doesn't exist.
+pub fn style(text: &str, styles: &str) -> Result<String, String> {
+    let codes = styles
+        .split_whitespace()
+        .map(|name| style_code(name).map(|c| c.to_string()).ok_or(name))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|name| format!("Unknown style '{}'", name))?;
+    if codes.is_empty() || !enabled() {
+        return Ok(text.to_owned());
+    }
+    Ok(format!("\x1b[{}m{}\x1b[0m", codes.join(";"), text))
+}
+
+/// Write an escape sequence to standard output straight away, if it goes to a terminal.
+pub fn control(sequence: &str) -> io::Result<()> {
+    if enabled() {
+        let mut stdout = io::stdout();
+        stdout.write_all(sequence.as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// The number of columns of the terminal, falling back to `COLUMNS` and then to 80.
+pub fn width() -> usize {
+    terminal_width()
+        .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(80)
+}
+
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer it's given.
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret == 0 && size.ws_col > 0 {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_width() -> Option<usize> {
+    None
+}
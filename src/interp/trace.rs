@@ -0,0 +1,39 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::vm::{Hooks, Value};
+
+/// How deeply the arguments and results shown in a trace show nested aggregates.
+const TRACE_DEPTH: usize = 2;
+
+/// Hooks printing every function call and the value it returns to standard error,
+/// indented by how deeply the calls are nested, for `--trace-calls`.
+pub fn call_tracer() -> Hooks {
+    let depth = Rc::new(Cell::new(0));
+    let call_depth = depth.clone();
+    let on_call = move |f: &Value, args: &[Value]| {
+        let args: Vec<_> = args.iter().map(show).collect();
+        let indent = "  ".repeat(call_depth.get());
+        eprintln!("{}-> {}({})", indent, f, args.join(", "));
+        call_depth.set(call_depth.get() + 1);
+        Ok(())
+    };
+    let on_return = move |val: &Value| {
+        depth.set(depth.get().saturating_sub(1));
+        eprintln!("{}<- {}", "  ".repeat(depth.get()), show(val));
+        Ok(())
+    };
+    Hooks {
+        on_call: Some(Box::new(on_call)),
+        on_return: Some(Box::new(on_return)),
+        ..Hooks::default()
+    }
+}
+
+/// Show a value in a trace, quoting strings to tell them apart from other values.
+fn show(val: &Value) -> String {
+    match val {
+        Value::Str(s) => format!("{:?}", s),
+        val => val.display_depth(TRACE_DEPTH).to_string(),
+    }
+}
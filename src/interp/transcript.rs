@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use super::repl::{Repl, CONTINUATION_PROMPT, PROMPT};
+use super::{output, read_source, Config};
+
+/// A piece of code from a transcript, along with what the REPL printed for it.
+struct Entry {
+    /// The line of the transcript the code starts on.
+    line: usize,
+    input: String,
+    expected: String,
+}
+
+/// Split a transcript into the pieces of code typed at the prompt and the output following them,
+/// which lasts until the next prompt or blank line. Anything else describes the transcript,
+/// and is skipped.
+fn parse(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    // Whether the lines which follow are the output of the last entry.
+    let mut in_output = false;
+    for (i, line) in text.lines().enumerate() {
+        let prompted = |prompt: &str| {
+            line.strip_prefix(prompt).or_else(|| {
+                line.strip_prefix(prompt.trim_end())
+                    .filter(|s| s.is_empty())
+            })
+        };
+        if let Some(code) = prompted(PROMPT) {
+            entries.push(Entry {
+                line: i + 1,
+                input: format!("{}\n", code),
+                expected: String::new(),
+            });
+            in_output = true;
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some(entry) if in_output => entry,
+            _ => continue,
+        };
+        if line.trim().is_empty() {
+            in_output = false;
+            continue;
+        }
+        match prompted(CONTINUATION_PROMPT) {
+            Some(code) if entry.expected.is_empty() => {
+                entry.input.push_str(code);
+                entry.input.push('\n');
+            }
+            _ => {
+                entry.expected.push_str(line);
+                entry.expected.push('\n');
+            }
+        }
+    }
+    entries
+}
+
+/// Replay the REPL session recorded in the transcript at `path`, reporting every piece of code
+/// whose output differs from the recorded one. Returns whether all of them matched.
+pub fn check_transcript<P: AsRef<Path>>(path: P, config: &Config) -> bool {
+    let path = path.as_ref();
+    let text = match read_source(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            return false;
+        }
+    };
+    let entries = parse(&text);
+    let mut repl = Repl::new(config, None);
+    let mut failed = 0;
+    for entry in &entries {
+        let (result, mut actual) = output::capture(|| repl.run(&entry.input));
+        match result {
+            Ok(val) => actual.push_str(&format!("{}\n", val)),
+            Err(err) => actual.push_str(&format!("{}\n", err)),
+        }
+        if actual.trim_end() != entry.expected.trim_end() {
+            failed += 1;
+            eprintln!("{}:{}: output differs", path.display(), entry.line);
+            for line in entry.input.lines() {
+                eprintln!("  {}", line);
+            }
+            eprintln!("expected:");
+            for line in entry.expected.trim_end().lines() {
+                eprintln!("  {}", line);
+            }
+            eprintln!("found:");
+            for line in actual.trim_end().lines() {
+                eprintln!("  {}", line);
+            }
+        }
+    }
+    println!(
+        "{}: {} passed, {} failed",
+        path.display(),
+        entries.len() - failed,
+        failed
+    );
+    failed == 0
+}
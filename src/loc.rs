@@ -1,7 +1,13 @@
+use std::fmt::{self, Display};
+
 #[derive(Debug, Clone, Copy)]
 pub struct SourceLocation {
     pub offset: usize,
     pub len: usize,
+    /// Line of the start of the location, counting from 1.
+    pub line: usize,
+    /// Column of the start of the location in characters, counting from 1.
+    pub column: usize,
 }
 
 impl SourceLocation {
@@ -20,17 +26,23 @@ impl SourceLocation {
 
     /// Return the line or lines that contain the object's location,
     /// and the location relative to that context.
-    fn context(self, source: &str) -> (&str, SourceLocation) {
-        let offset = source[..=self.offset]
-            .rfind('\n')
-            .map(|i| i + 1)
-            .unwrap_or(0);
-        let end_offset = source[self.end_offset()..]
+    pub fn context(self, source: &str) -> (&str, SourceLocation) {
+        let start = self.offset
+            - source[..self.offset]
+                .chars()
+                .rev()
+                .take(self.column - 1)
+                .map(char::len_utf8)
+                .sum::<usize>();
+        let end = source[self.end_offset()..]
             .find('\n')
+            .map(|i| i + self.end_offset())
             .unwrap_or(source.len());
-        let len = end_offset - offset;
-        let loc = SourceLocation { offset, len };
-        (&source[offset..end_offset], loc)
+        let loc = SourceLocation {
+            offset: self.offset - start,
+            ..self
+        };
+        (&source[start..end], loc)
     }
 
     fn end_offset(&self) -> usize {
@@ -38,6 +50,12 @@ impl SourceLocation {
     }
 }
 
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 pub trait Locate {
     fn location(&self) -> SourceLocation;
 }
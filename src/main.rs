@@ -1,17 +1,265 @@
+mod bundle;
 mod compile;
+mod diag;
 mod interp;
 mod loc;
+mod project;
 mod scan;
+mod suggest;
 mod vm;
+mod xref;
 
-use std::env::args;
+use std::env::{self, args};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+use bundle::Bundle;
+use diag::Level;
+use interp::{bench, Config};
+use vm::limits::CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn usage() {
+    eprintln!("Usage: oxide [options] [script | -] [args...]");
+    eprintln!("       oxide [options] run [args...]");
+    eprintln!("       oxide [options] bundle <script> -o <output>");
+    eprintln!("       oxide [options] bench <script> [--baseline <file>] [--save <file>]");
+    eprintln!("       oxide [options] transcript <file>");
+    eprintln!("       oxide --dump-calls <script>");
+    eprintln!("       oxide --dump-tokens <script>");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("    -W <warning>       Report the given warning");
+    eprintln!("    -A <warning>       Allow the given warning, without reporting it");
+    eprintln!("    --deny-warnings    Treat warnings as errors, and don't run the script");
+    eprintln!("    --path <dir>       Look for imported modules in the given directory");
+    eprintln!("    --error-format <f> Print errors and warnings as human readable text, or json");
+    eprintln!("    --print-last       Print the value of the script's last line when it finishes");
+    eprintln!("    --allow-float-index Truncate indices with a fractional part instead of failing");
+    eprintln!("    --trace-calls      Print every function call and what it returns");
+    eprintln!("    --deterministic    Count log timestamps and number temporary files in order,");
+    eprintln!("                       for repeatable runs");
+    eprintln!("    --max-depth <n>    Stop scripts nesting function calls deeper than n");
+    eprintln!("    --max-steps <n>    Stop scripts after running n instructions");
+    eprintln!("    --max-heap <size>  Stop scripts once more than size bytes are allocated,");
+    eprintln!("                       which can be given in K, M or G");
+}
+
+/// Exit with a failure status if the command didn't succeed, so that callers can tell.
+fn exit_on_failure(succeeded: bool) {
+    if !succeeded {
+        process::exit(1);
+    }
+}
+
+/// Run the entry point of the project containing the current directory.
+fn run_command(config: &Config) -> bool {
+    let workspace = env::current_dir()
+        .map_err(|err| err.to_string())
+        .and_then(|dir| project::Workspace::find(&dir).map_err(|err| err.to_string()));
+    match workspace {
+        Ok(workspace) => interp::run_project(&workspace, config),
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
+    }
+}
+
+fn bundle_command(mut args: impl Iterator<Item = String>, config: &Config) -> bool {
+    let mut path = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next().map(PathBuf::from),
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                usage();
+                return false;
+            }
+        }
+    }
+    match (path, output) {
+        (Some(path), Some(output)) => interp::bundle_file(path, &output, config),
+        _ => {
+            usage();
+            false
+        }
+    }
+}
+
+fn bench_command(mut args: impl Iterator<Item = String>, config: &Config) -> bool {
+    let mut path = None;
+    let mut options = bench::Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--baseline" => options.baseline = args.next().map(Into::into),
+            "--save" => options.save = args.next().map(Into::into),
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                usage();
+                return false;
+            }
+        }
+    }
+    match path {
+        Some(path) => interp::bench_file(path, &options, config),
+        None => {
+            usage();
+            false
+        }
+    }
+}
+
+fn parse_count<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for {}", value, flag))
+}
+
+/// Parse a number of bytes, optionally followed by a K, M or G suffix.
+fn parse_size(flag: &str, value: &str) -> Result<usize, String> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    parse_count::<usize>(flag, digits)?
+        .checked_mul(unit)
+        .ok_or_else(|| format!("Invalid value '{}' for {}", value, flag))
+}
+
+/// The commands which come in place of a script, and take arguments of their own.
+const COMMANDS: &[&str] = &[
+    "bench",
+    "bundle",
+    "transcript",
+    "--dump-calls",
+    "--dump-tokens",
+];
+
+/// Take the options applying to every command out of `args`, returning the remaining arguments.
+/// The arguments following a script (or `run`) are left for the script.
+fn parse_config(mut args: impl Iterator<Item = String>) -> Result<(Config, Vec<String>), String> {
+    let mut config = Config::default();
+    let mut rest = Vec::new();
+    while let Some(arg) = args.next() {
+        let level = match arg.as_str() {
+            "-W" => Level::Warn,
+            "-A" => Level::Allow,
+            "--deny-warnings" => {
+                config.policy.deny_warnings = true;
+                continue;
+            }
+            "--error-format" => {
+                let format = args
+                    .next()
+                    .ok_or_else(|| format!("Missing format after {}", arg))?;
+                config.error_format = format.parse()?;
+                continue;
+            }
+            "--print-last" => {
+                config.print_last = true;
+                continue;
+            }
+            "--allow-float-index" => {
+                config.allow_float_index = true;
+                continue;
+            }
+            "--trace-calls" => {
+                config.trace_calls = true;
+                continue;
+            }
+            "--deterministic" => {
+                config.deterministic = true;
+                continue;
+            }
+            "--path" => {
+                let dir = args
+                    .next()
+                    .ok_or_else(|| format!("Missing directory after {}", arg))?;
+                config.paths.push(dir.into());
+                continue;
+            }
+            "--max-depth" | "--max-steps" | "--max-heap" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value after {}", arg))?;
+                let limits = &mut config.limits;
+                match arg.as_str() {
+                    "--max-depth" => limits.max_depth = Some(parse_count(&arg, &value)?),
+                    "--max-steps" => limits.max_steps = Some(parse_count(&arg, &value)?),
+                    _ => limits.max_heap = Some(parse_size(&arg, &value)?),
+                }
+                continue;
+            }
+            _ => {
+                let is_script = rest.is_empty() && !COMMANDS.contains(&arg.as_str());
+                rest.push(arg);
+                if is_script {
+                    // Everything after the script is for the script itself.
+                    config.args = args.collect();
+                    break;
+                }
+                continue;
+            }
+        };
+        let code = args
+            .next()
+            .ok_or_else(|| format!("Missing warning name after {}", arg))?;
+        config.policy.set(&code, level)?;
+    }
+    Ok((config, rest))
+}
 
 fn main() {
-    if let Some(path) = args().nth(1) {
-        if let Err(e) = interp::run_file(path) {
-            println!("{}", e);
+    // An executable made by `oxide bundle` only ever runs the script bundled into it.
+    match Bundle::load() {
+        Ok(Some(bundle)) => {
+            // Every argument belongs to the script, since there's no script path to give.
+            let config = Config {
+                args: args().skip(1).collect(),
+                ..Config::default()
+            };
+            return exit_on_failure(interp::run_bundle(bundle, &config));
+        }
+        Ok(None) => (),
+        Err(err) => {
+            eprintln!("{}", err);
+            return exit_on_failure(false);
+        }
+    }
+    let (config, args) = match parse_config(args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}", err);
+            return usage();
         }
-    } else {
-        interp::repl();
+    };
+    diag::set_format(config.error_format);
+    let mut args = args.into_iter();
+    match args.next() {
+        Some(cmd) if cmd == "bench" => exit_on_failure(bench_command(args, &config)),
+        Some(cmd) if cmd == "bundle" => exit_on_failure(bundle_command(args, &config)),
+        Some(cmd) if cmd == "run" && args.len() == 0 => exit_on_failure(run_command(&config)),
+        Some(cmd) if cmd == "transcript" => match args.next() {
+            Some(path) => exit_on_failure(interp::check_transcript(path, &config)),
+            None => usage(),
+        },
+        Some(flag) if flag == "--dump-calls" => match args.next() {
+            Some(path) => interp::dump_calls(path),
+            None => usage(),
+        },
+        Some(flag) if flag == "--dump-tokens" => match args.next() {
+            Some(path) => interp::dump_tokens(path),
+            None => usage(),
+        },
+        Some(path) if path == "-" => exit_on_failure(interp::run_stdin(&config)),
+        Some(path) => exit_on_failure(interp::run_file(path, &config)),
+        None => interp::repl(&config),
     }
 }
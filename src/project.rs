@@ -0,0 +1,119 @@
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// The name of the file marking the root directory of a project.
+pub const MANIFEST_NAME: &str = "oxide.toml";
+
+/// The contents of a project's `oxide.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub project: Project,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// The script run by `oxide run`, relative to the project's root.
+    #[serde(default = "default_entry")]
+    pub entry: PathBuf,
+    /// Directories searched for modules, relative to the project's root.
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+}
+
+fn default_entry() -> PathBuf {
+    "main.o2".into()
+}
+
+impl Default for Project {
+    fn default() -> Self {
+        Project {
+            name: None,
+            version: None,
+            description: None,
+            entry: default_entry(),
+            paths: Vec::new(),
+        }
+    }
+}
+
+/// A project, as described by its manifest.
+#[derive(Debug)]
+pub struct Workspace {
+    /// The directory containing the manifest.
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Workspace {
+    /// Find the project containing `dir`, by looking for a manifest in it and then in each of its parents.
+    pub fn find(dir: &Path) -> Result<Workspace> {
+        for root in dir.ancestors() {
+            let path = root.join(MANIFEST_NAME);
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::IO(path, err)),
+            };
+            let manifest = toml::from_str(&text).map_err(|err| Error::Parse(path, err))?;
+            return Ok(Workspace {
+                root: root.to_owned(),
+                manifest,
+            });
+        }
+        Err(Error::NotFound)
+    }
+
+    pub fn entry(&self) -> PathBuf {
+        self.root.join(&self.manifest.project.entry)
+    }
+
+    /// The directories modules are searched in.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let paths = self.manifest.project.paths.iter();
+        paths.map(|path| self.root.join(path)).collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// No manifest in the directory or any of its parents.
+    NotFound,
+    IO(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(
+                f,
+                "Could not find {} in this directory or any of its parents",
+                MANIFEST_NAME
+            ),
+            Error::IO(path, err) => write!(f, "{}: {}", path.display(), err),
+            Error::Parse(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NotFound => None,
+            Error::IO(_, err) => Some(err),
+            Error::Parse(_, err) => Some(err),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
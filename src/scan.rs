@@ -1,8 +1,16 @@
+mod source;
+
 use std::fmt::{self, Display};
+use std::io::{self, BufRead};
 use std::num::ParseFloatError;
+use std::rc::Rc;
+
+use num_bigint::{BigInt, ParseBigIntError};
+use unicode_ident::{is_xid_continue, is_xid_start};
 
 use crate::loc::{Locate, SourceLocation};
 use crate::vm::Value;
+use source::{ReadSource, StrSource};
 
 #[derive(Debug)]
 pub enum TokenType {
@@ -14,16 +22,32 @@ pub enum TokenType {
     Then,
     Else,
     While,
+    For,
+    In,
+    Is,
+    Switch,
+    Case,
+    Default,
+    Fallthrough,
+    Break,
+    Continue,
+    Return,
     Function,
+    Bench,
     Minus,
     Plus,
     Slash,
     Star,
+    Pipe,
+    Ampersand,
+    Question,
     Arrow,
     LeftParen,
     RightParen,
     LeftBracket,
     RightBracket,
+    LeftSquare,
+    RightSquare,
     And,
     Or,
     Equal,
@@ -36,6 +60,8 @@ pub enum TokenType {
     LessEqual,
     Not,
     Comma,
+    Dot,
+    Colon,
 }
 
 use TokenType::*;
@@ -54,16 +80,32 @@ impl Display for TokenType {
                 Then => "then",
                 Else => "else",
                 While => "while",
+                For => "for",
+                In => "in",
+                Is => "is",
+                Switch => "switch",
+                Case => "case",
+                Default => "default",
+                Fallthrough => "fallthrough",
+                Break => "break",
+                Continue => "continue",
+                Return => "return",
                 Function => "fn",
+                Bench => "bench",
                 Minus => "-",
                 Plus => "+",
                 Slash => "/",
                 Star => "*",
+                Pipe => "|",
+                Ampersand => "&",
+                Question => "?",
                 Arrow => "->",
                 LeftParen => "(",
                 RightParen => ")",
                 LeftBracket => "{",
                 RightBracket => "}",
+                LeftSquare => "[",
+                RightSquare => "]",
                 And => "and",
                 Or => "or",
                 Not => "not",
@@ -76,6 +118,8 @@ impl Display for TokenType {
                 Less => "<",
                 LessEqual => "<=",
                 Comma => ",",
+                Dot => ".",
+                Colon => ":",
             }
         )
     }
@@ -85,89 +129,244 @@ impl Display for TokenType {
 pub struct Token {
     pub ttype: TokenType,
     pub loc: SourceLocation,
+    /// Whether the token is the first one on its line.
+    pub starts_line: bool,
+    /// The text of the token, exactly as written in the source.
+    pub lexeme: String,
+    /// The whitespace and comments between the previous token and this one.
+    pub trivia: String,
 }
 
-pub struct TokenStream<'a> {
-    unread: &'a str,
+/// Splits the characters of a script into tokens.
+/// `S` is the source of the characters, read one at a time as needed.
+pub struct TokenStream<S> {
+    source: S,
+    peeked: Option<char>,
+    /// An error which stopped the source from being read any further.
+    failed: Option<io::Error>,
+    /// The text of the token being scanned.
+    lexeme: String,
+    /// The whitespace and comments skipped since the last token.
+    trivia: String,
     pos: usize,
+    line: usize,
+    column: usize,
+    /// The line the previous token ended on.
+    prev_line: usize,
 }
 
-impl<'a> TokenStream<'a> {
+impl<'a> TokenStream<StrSource<'a>> {
     pub fn new(s: &'a str) -> Self {
-        TokenStream { unread: s, pos: 0 }
+        TokenStream::from_source(StrSource::new(s))
+    }
+}
+
+impl<R: BufRead> TokenStream<ReadSource<R>> {
+    /// Scan a script as it is being read, without loading all of it first.
+    pub fn from_reader(reader: R) -> Self {
+        TokenStream::from_source(ReadSource::new(reader))
+    }
+}
+
+impl<S> TokenStream<S>
+where
+    S: Iterator<Item = io::Result<char>>,
+{
+    fn from_source(source: S) -> Self {
+        TokenStream {
+            source,
+            peeked: None,
+            failed: None,
+            lexeme: String::new(),
+            trivia: String::new(),
+            pos: 0,
+            line: 1,
+            column: 1,
+            prev_line: 0,
+        }
     }
 
-    fn peek(&self) -> Option<char> {
-        self.unread.chars().next()
+    fn peek(&mut self) -> Option<char> {
+        if self.peeked.is_none() && self.failed.is_none() {
+            match self.source.next() {
+                Some(Ok(c)) => self.peeked = Some(c),
+                Some(Err(err)) => self.failed = Some(err),
+                None => (),
+            }
+        }
+        self.peeked
     }
 
-    fn advance(&mut self, cnt: usize) -> &str {
-        let s = &self.unread[..cnt];
-        self.unread = &self.unread[cnt..];
-        self.pos += cnt;
-        s
+    /// Consume the next character, adding it to the current lexeme.
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.peeked = None;
+        self.lexeme.push(c);
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
     }
 
-    fn advance_while<F>(&mut self, pattern: F) -> &str
+    fn advance_while<F>(&mut self, pattern: F)
     where
         F: Fn(char) -> bool,
     {
-        let i = self
-            .unread
-            .char_indices()
-            .skip_while(|(_, c)| pattern(*c))
-            .map(|x| x.0)
-            .next()
-            .unwrap_or_else(|| self.unread.len());
-        self.advance(i)
+        while self.peek().is_some_and(&pattern) {
+            self.advance();
+        }
     }
 
     fn num_literal(&mut self) -> std::result::Result<TokenType, ErrorKind> {
-        let s = self.unread;
-        let offset = self.pos;
-        self.advance_while(char::is_numeric);
-        if let Some('.') = self.peek() {
-            self.advance(1);
-            self.advance_while(char::is_numeric);
+        let is_digit = |c: char| c.is_ascii_digit() || c == '_';
+        self.advance_while(is_digit);
+        if self.lexeme == "0" {
+            let radix = match self.peek() {
+                Some('x') => 16,
+                Some('o') => 8,
+                Some('b') => 2,
+                _ => 10,
+            };
+            if radix != 10 {
+                self.advance();
+                return self.radix_literal(radix);
+            }
+        }
+        let is_int = self.peek() != Some('.');
+        if !is_int {
+            self.advance();
+            self.advance_while(is_digit);
+        }
+        // Underscores may only appear between two digits.
+        let bytes = self.lexeme.as_bytes();
+        let between_digits = |i: usize| {
+            i > 0
+                && bytes[i - 1].is_ascii_digit()
+                && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)
+        };
+        if let Some(i) = (0..bytes.len()).find(|&i| bytes[i] == b'_' && !between_digits(i)) {
+            return Err(ErrorKind::MisplacedUnderscore(i));
+        }
+        let digits = self.lexeme.replace('_', "");
+        if let Some('n') = self.peek() {
+            self.advance();
+            return digits
+                .parse::<BigInt>()
+                .map(|num| Literal(num.into()))
+                .map_err(ErrorKind::ParseBigInt);
         }
-        let len = self.pos - offset;
-        s[..len]
+        if is_int {
+            // Literals too big for an int are still exact, as big integers.
+            return match digits.parse::<i64>() {
+                Ok(num) => Ok(Literal(Value::Int(num))),
+                Err(_) => digits
+                    .parse::<BigInt>()
+                    .map(|num| Literal(num.into()))
+                    .map_err(ErrorKind::ParseBigInt),
+            };
+        }
+        digits
             .parse::<f64>()
             .map(|num| Literal(Value::Num(num)))
             .map_err(ErrorKind::ParseNum)
     }
 
-    fn str_literal(&mut self) -> std::result::Result<TokenType, ErrorKind> {
-        let s = self.unread;
-        let offset = self.pos;
+    /// Scan the digits of a hexadecimal, octal or binary literal, after its prefix.
+    fn radix_literal(&mut self, radix: u32) -> std::result::Result<TokenType, ErrorKind> {
+        const PREFIX: usize = 2;
+        // Letters are taken in too, so that a digit out of range is reported
+        // instead of being scanned as an identifier right after the number.
+        self.advance_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        let is_big = self.lexeme.ends_with('n');
+        let end = self.lexeme.len() - usize::from(is_big);
+        let bytes = &self.lexeme.as_bytes()[..end];
+        let is_digit = |i: usize| (PREFIX..end).contains(&i) && bytes[i] != b'_';
+        for (i, &byte) in bytes.iter().enumerate().skip(PREFIX) {
+            let c = char::from(byte);
+            if c == '_' && !(is_digit(i - 1) && is_digit(i + 1)) {
+                return Err(ErrorKind::MisplacedUnderscore(i));
+            } else if c != '_' && !c.is_digit(radix) {
+                return Err(ErrorKind::InvalidDigit {
+                    at: i,
+                    digit: c,
+                    radix,
+                });
+            }
+        }
+        let digits = self.lexeme[PREFIX..end].replace('_', "");
+        if digits.is_empty() {
+            return Err(ErrorKind::MissingDigits(radix));
+        }
+        // Literals too big for an int are still exact, as big integers.
+        let big = || BigInt::parse_bytes(digits.as_bytes(), radix).expect("digits were checked");
+        match i64::from_str_radix(&digits, radix) {
+            Ok(num) if !is_big => Ok(Literal(Value::Int(num))),
+            _ => Ok(Literal(big().into())),
+        }
+    }
+
+    /// Scan the rest of a string literal, whose opening quote is at `quote`.
+    fn str_literal(&mut self, quote: SourceLocation) -> std::result::Result<TokenType, ErrorKind> {
         self.advance_while(|c| c != '"');
         if let Some('"') = self.peek() {
-            let len = self.pos - offset;
-            self.advance(1);
-            let s = &s[..len];
-            Ok(Literal(Value::Str(s.to_owned())))
+            let s = Value::new_str(&self.lexeme[1..]);
+            self.advance();
+            Ok(Literal(s))
         } else {
-            Err(ErrorKind::UnmatchedQuote)
+            Err(ErrorKind::UnmatchedQuote(quote))
         }
     }
 
-    fn skip_block_comment(&mut self) -> std::result::Result<(), ErrorKind> {
+    /// Skip the rest of a block comment, which was opened at `opening`.
+    fn skip_block_comment(
+        &mut self,
+        opening: SourceLocation,
+    ) -> std::result::Result<(), ErrorKind> {
         loop {
             self.advance_while(|c| c != '*');
-            match self.peek() {
-                Some(x) => {
-                    self.advance(1);
-                    if x == '/' {
-                        self.advance(1);
+            match self.advance() {
+                Some(_) => {
+                    if let Some('/') = self.peek() {
+                        self.advance();
                         return Ok(());
                     }
                 }
                 None => {
-                    return Err(ErrorKind::UnmatchedComment);
+                    return Err(ErrorKind::UnmatchedComment(opening));
                 }
             }
         }
     }
+
+    /// An empty location at the current position.
+    fn here(&self) -> SourceLocation {
+        SourceLocation {
+            offset: self.pos,
+            len: 0,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+/// Identifiers follow the default syntax of Unicode Standard Annex #31:
+/// a character with the XID_Start property (or an underscore),
+/// followed by any number of characters with the XID_Continue property.
+fn is_ident_start(c: char) -> bool {
+    c == '_' || is_xid_start(c)
+}
+
+fn radix_name(radix: u32) -> &'static str {
+    match radix {
+        16 => "hexadecimal",
+        8 => "octal",
+        2 => "binary",
+        _ => "decimal",
+    }
 }
 
 fn keyword(s: &str) -> Option<TokenType> {
@@ -178,7 +377,18 @@ fn keyword(s: &str) -> Option<TokenType> {
         "then" => Some(Then),
         "else" => Some(Else),
         "while" => Some(While),
+        "for" => Some(For),
+        "in" => Some(In),
+        "is" => Some(Is),
+        "switch" => Some(Switch),
+        "case" => Some(Case),
+        "default" => Some(Default),
+        "fallthrough" => Some(Fallthrough),
+        "break" => Some(Break),
+        "continue" => Some(Continue),
+        "return" => Some(Return),
         "fn" => Some(Function),
+        "bench" => Some(Bench),
         "and" => Some(And),
         "or" => Some(Or),
         "not" => Some(Not),
@@ -189,40 +399,61 @@ fn keyword(s: &str) -> Option<TokenType> {
     }
 }
 
-impl<'a> Iterator for TokenStream<'a> {
+impl<S> Iterator for TokenStream<S>
+where
+    S: Iterator<Item = io::Result<char>>,
+{
     type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.advance_while(char::is_whitespace);
+        // Whatever was scanned so far (including comments, which end up here
+        // through the recursive calls below) belongs to the trivia.
+        self.trivia.push_str(&self.lexeme);
+        self.lexeme.clear();
         let offset = self.pos;
-        let c = self.peek()?;
-        let result = if c.is_numeric() {
+        let start = self.here();
+        let opening = |len| SourceLocation { len, ..start };
+        let c = match self.peek() {
+            Some(c) => c,
+            None => {
+                let err = self.failed.take()?;
+                let kind = ErrorKind::Read(Rc::new(err));
+                return Some(Err(Error { kind, loc: start }));
+            }
+        };
+        let result = if c.is_ascii_digit() {
             self.num_literal()
-        } else if c.is_alphabetic() || c == '_' {
-            let s = self.advance_while(|c| c.is_alphanumeric() || c == '_');
-            Ok(keyword(s).unwrap_or_else(|| Identifier(s.to_owned())))
+        } else if is_ident_start(c) {
+            self.advance_while(is_xid_continue);
+            Ok(keyword(&self.lexeme).unwrap_or_else(|| Identifier(self.lexeme.clone())))
         } else {
-            self.advance(1);
+            self.advance();
             match c {
-                '"' => self.str_literal(),
+                '"' => self.str_literal(opening(1)),
                 '+' => Ok(Plus),
                 ',' => Ok(Comma),
+                '.' => Ok(Dot),
+                ':' => Ok(Colon),
                 '-' => match self.peek() {
                     Some('>') => {
-                        self.advance(1);
+                        self.advance();
                         Ok(Arrow)
                     }
                     _ => Ok(Minus),
                 },
                 '*' => Ok(Star),
+                '|' => Ok(Pipe),
+                '&' => Ok(Ampersand),
+                '?' => Ok(Question),
                 '/' => match self.peek() {
                     Some('/') => {
                         self.advance_while(|c| c != '\n');
                         return self.next();
                     }
                     Some('*') => {
-                        self.advance(1);
-                        match self.skip_block_comment() {
+                        self.advance();
+                        match self.skip_block_comment(opening(2)) {
                             Ok(()) => return self.next(),
                             Err(e) => Err(e),
                         }
@@ -233,30 +464,32 @@ impl<'a> Iterator for TokenStream<'a> {
                 ')' => Ok(RightParen),
                 '{' => Ok(LeftBracket),
                 '}' => Ok(RightBracket),
+                '[' => Ok(LeftSquare),
+                ']' => Ok(RightSquare),
                 '=' => match self.peek() {
                     Some('=') => {
-                        self.advance(1);
+                        self.advance();
                         Ok(EqualEqual)
                     }
                     _ => Ok(Equal),
                 },
                 '!' => match self.peek() {
                     Some('=') => {
-                        self.advance(1);
+                        self.advance();
                         Ok(BangEqual)
                     }
                     _ => Ok(Bang),
                 },
                 '>' => match self.peek() {
                     Some('=') => {
-                        self.advance(1);
+                        self.advance();
                         Ok(GreaterEqual)
                     }
                     _ => Ok(Greater),
                 },
                 '<' => match self.peek() {
                     Some('=') => {
-                        self.advance(1);
+                        self.advance();
                         Ok(LessEqual)
                     }
                     _ => Ok(Less),
@@ -264,21 +497,67 @@ impl<'a> Iterator for TokenStream<'a> {
                 c => Err(ErrorKind::Unrecognized(c)),
             }
         };
-        let len = self.pos - offset;
-        let loc = SourceLocation { offset, len };
+        let loc = SourceLocation {
+            len: self.pos - offset,
+            ..start
+        };
+        let result = match self.failed.take() {
+            Some(err) => Err(ErrorKind::Read(Rc::new(err))),
+            None => result,
+        };
+        let starts_line = start.line > self.prev_line;
+        self.prev_line = self.line;
+        let trivia = std::mem::take(&mut self.trivia);
         Some(
             result
-                .map(|ttype| Token { ttype, loc })
-                .map_err(|kind| Error { kind, loc }),
+                .map(|ttype| Token {
+                    ttype,
+                    loc,
+                    starts_line,
+                    lexeme: std::mem::take(&mut self.lexeme),
+                    trivia,
+                })
+                .map_err(|kind| {
+                    // Unterminated strings and comments are only noticed at the end of input.
+                    let loc = match kind {
+                        ErrorKind::UnmatchedQuote(_) | ErrorKind::UnmatchedComment(_) => {
+                            self.here()
+                        }
+                        // Point at the underscore itself, rather than the whole number.
+                        ErrorKind::MisplacedUnderscore(i)
+                        | ErrorKind::InvalidDigit { at: i, .. } => SourceLocation {
+                            offset: loc.offset + i,
+                            len: 1,
+                            column: loc.column + i,
+                            ..loc
+                        },
+                        _ => loc,
+                    };
+                    Error { kind, loc }
+                }),
         )
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum ErrorKind {
-    UnmatchedQuote,
-    UnmatchedComment,
+    /// A string literal which was never closed, and the location of its opening quote.
+    UnmatchedQuote(SourceLocation),
+    /// A block comment which was never closed, and the location of its opening `/*`.
+    UnmatchedComment(SourceLocation),
     ParseNum(ParseFloatError),
+    ParseBigInt(ParseBigIntError),
+    /// An underscore in a number which isn't between two digits, at the given byte of the number.
+    MisplacedUnderscore(usize),
+    /// A character which isn't a digit in the base of a number, at the given byte of the number.
+    InvalidDigit {
+        at: usize,
+        digit: char,
+        radix: u32,
+    },
+    /// A `0x`, `0o` or `0b` prefix with no digits after it, for numbers of the given base.
+    MissingDigits(u32),
+    Read(Rc<io::Error>),
     Unrecognized(char),
 }
 
@@ -288,6 +567,27 @@ pub struct Error {
     loc: SourceLocation,
 }
 
+impl Error {
+    /// A secondary location related to the error, along with a label describing it.
+    pub fn note(&self) -> Option<(&'static str, SourceLocation)> {
+        match self.kind {
+            ErrorKind::UnmatchedQuote(start) | ErrorKind::UnmatchedComment(start) => {
+                Some(("started here", start))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the error is a string or comment still open when the source ended,
+    /// which more source could complete.
+    pub fn is_unterminated(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::UnmatchedQuote(_) | ErrorKind::UnmatchedComment(_)
+        )
+    }
+}
+
 impl Locate for Error {
     fn location(&self) -> SourceLocation {
         self.loc
@@ -297,9 +597,25 @@ impl Locate for Error {
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.kind {
-            ErrorKind::UnmatchedQuote => write!(f, "Unmatched quote"),
-            ErrorKind::UnmatchedComment => write!(f, "Unterminated block comment"),
+            ErrorKind::UnmatchedQuote(_) => write!(f, "Unmatched quote"),
+            ErrorKind::UnmatchedComment(_) => write!(f, "Unterminated block comment"),
             ErrorKind::ParseNum(cause) => write!(f, "Unable to parse number: {}", cause),
+            ErrorKind::ParseBigInt(cause) => write!(f, "Unable to parse big integer: {}", cause),
+            ErrorKind::MisplacedUnderscore(_) => {
+                write!(f, "Underscores in numbers must be placed between digits")
+            }
+            ErrorKind::InvalidDigit { digit, radix, .. } => {
+                write!(
+                    f,
+                    "Invalid digit '{}' in {} number",
+                    digit,
+                    radix_name(*radix)
+                )
+            }
+            ErrorKind::MissingDigits(radix) => {
+                write!(f, "Expected {} digits after the prefix", radix_name(*radix))
+            }
+            ErrorKind::Read(cause) => write!(f, "Unable to read source: {}", cause),
             ErrorKind::Unrecognized(c) => write!(f, "Invalid token '{}'", c),
         }
     }
@@ -309,6 +625,8 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match &self.kind {
             ErrorKind::ParseNum(cause) => Some(cause),
+            ErrorKind::ParseBigInt(cause) => Some(cause),
+            ErrorKind::Read(cause) => Some(&**cause),
             _ => None,
         }
     }
@@ -0,0 +1,55 @@
+use std::io::{self, BufRead};
+use std::str::Chars;
+
+/// Characters of a script which is already entirely in memory.
+pub struct StrSource<'a>(Chars<'a>);
+
+impl<'a> StrSource<'a> {
+    pub fn new(s: &'a str) -> Self {
+        StrSource(s.chars())
+    }
+}
+
+impl Iterator for StrSource<'_> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(Ok)
+    }
+}
+
+/// Characters of a script read incrementally, one line at a time.
+pub struct ReadSource<R> {
+    reader: R,
+    line: String,
+    pos: usize,
+}
+
+impl<R: BufRead> ReadSource<R> {
+    pub fn new(reader: R) -> Self {
+        ReadSource {
+            reader,
+            line: String::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ReadSource<R> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => (),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        let c = self.line[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(Ok(c))
+    }
+}
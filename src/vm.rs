@@ -1,89 +1,350 @@
+pub mod decimal;
+pub mod hooks;
+pub mod json;
+pub mod limits;
+mod reload;
+mod snapshot;
 mod value;
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto as _;
 use std::fmt::{self, Display};
+use std::io;
 use std::num::TryFromIntError;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 
-pub use value::Value;
+use crate::loc::SourceLocation;
+use crate::suggest;
+
+pub use hooks::Hooks;
+pub use limits::{Limit, Limits};
+pub use snapshot::Snapshot;
+pub use value::{Arity, Key, Value, WeakRef};
 
 #[derive(Debug, Clone)]
 pub enum Instruction {
     Push(Value),
-    GetLocal(u16),
-    SetLocal(u16),
+    GetLocal(u32),
+    SetLocal(u32),
     GetGlobal(String),
     SetGlobal(String),
+    /// Like SetGlobal, but declaring the global, which may replace a protected one.
+    DefineGlobal(String),
     Pop,
+    /// Push a copy of the value on top of the stack.
+    Dup,
+    /// Exchange the top two values of the stack.
+    Swap,
     // Dumb hacks
     SaveReturn,
     RestoreReturn,
-    Jump(i16),
-    JumpIfFalse(i16),
-    JumpIfTrue(i16),
+    Jump(i32),
+    JumpIfFalse(i32),
+    JumpIfTrue(i32),
     Call(u16),
+    /// Like Call followed by Unpack, except that a function returning several values
+    /// hands them over as they are, without putting them in an array first.
+    CallUnpack(u16, u16),
+    /// Return the value on top of the stack, discarding the rest of the function's frame.
     Ret,
+    /// Return the given number of values on top of the stack, which the caller gets as an array
+    /// unless it unpacks them.
+    RetMany(u16),
+    /// Unwrap an `ok` value, or return early from the function with an `err` or null.
+    Try,
+    MakeArray(u16),
+    /// Replace an array with its elements, which must number exactly as many as given.
+    Unpack(u16),
+    /// Replace a value with an array of the elements a for loop goes through.
+    IntoIter,
+    /// Advance a for loop, whose array and position are in the given local and the one after it.
+    /// Pushes the next element and true, or null and false once there are none left.
+    Next(u32),
     Add,
     Sub,
+    /// Like Add, for operands the compiler knows to be numbers.
+    AddNum,
+    /// Like Sub, for operands the compiler knows to be numbers.
+    SubNum,
     Mul,
     Div,
+    BitOr,
+    BitAnd,
     Neg,
     Not,
+    /// Replace a value and the index above it with the element at that index, with `Value::index`.
+    Index,
+    /// Replace a map with null, declaring each of its entries as a global named after its key.
+    ImportAll,
+    /// Replace an array or map, an index and a value above it with the value,
+    /// storing it at that index with `Value::set_index`.
+    SetIndex,
     Equal,
+    /// Check whether two values are the same, with `Value::is`.
+    Is,
     Less,
+    /// Like Less, for operands the compiler knows to be numbers.
+    LessNum,
     Greater,
     Temp, // Panics if encountered in code
 }
 
-pub type Chunk = Rc<Vec<Instruction>>;
+impl Instruction {
+    /// How many values running the instruction adds to the stack (or removes, if negative),
+    /// as long as it doesn't enter or leave a function.
+    /// Returns None for instructions with no fixed effect.
+    pub fn stack_effect(&self) -> Option<isize> {
+        use Instruction::*;
+        let effect = match self {
+            Push(_) | GetLocal(_) | GetGlobal(_) | RestoreReturn | Dup => 1,
+            Swap => 0,
+            SetLocal(_) | SetGlobal(_) | DefineGlobal(_) | Jump(_) | JumpIfFalse(_)
+            | JumpIfTrue(_) => 0,
+            Try | Neg | Not | ImportAll => 0,
+            Pop | SaveReturn => -1,
+            Add | Sub | Mul | Div | BitOr | BitAnd | Equal | Is | Less | Greater => -1,
+            Index => -1,
+            SetIndex => -2,
+            AddNum | SubNum | LessNum => -1,
+            // The arguments and the function itself are replaced by its result.
+            Call(argc) => -(*argc as isize),
+            CallUnpack(argc, len) => *len as isize - *argc as isize - 1,
+            MakeArray(len) => 1 - *len as isize,
+            Unpack(len) => *len as isize - 1,
+            IntoIter => 0,
+            Next(_) => 2,
+            Ret | RetMany(_) | Temp => return None,
+        };
+        Some(effect)
+    }
+}
+
+/// The compiled code of a function, along with what's known about the function itself.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub name: Option<String>,
+    pub arity: usize,
+    /// The most local variables the function has alive at the same time.
+    pub frame_size: usize,
+    /// Where the function was declared, if it comes from a `fn` expression.
+    pub loc: Option<SourceLocation>,
+}
+
+impl Chunk {
+    /// A nameless chunk running `code`, such as a REPL line.
+    pub fn script(code: Vec<Instruction>) -> Self {
+        Chunk {
+            code,
+            ..Chunk::default()
+        }
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fn {}", self.name.as_deref().unwrap_or("(anonymous)"))?;
+        if let Some(loc) = self.loc {
+            write!(f, " ({})", loc)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct CodeLocation {
-    chunk: Chunk,
+    chunk: Rc<Chunk>,
     ip: usize,
 }
 
 impl CodeLocation {
-    pub fn new(chunk: Chunk) -> Self {
+    pub fn new(chunk: Rc<Chunk>) -> Self {
         CodeLocation { chunk, ip: 0 }
     }
 
     pub fn is_at_end(&self) -> bool {
-        self.ip == self.chunk.len()
+        self.ip == self.chunk.code.len()
     }
 
-    pub fn jump(&mut self, offset: i16) -> Result<()> {
+    pub fn jump(&mut self, offset: i32) -> Result<()> {
         let mut ip: isize = self.ip.try_into()?;
-        ip += isize::from(offset);
+        let offset: isize = offset.try_into()?;
+        ip += offset;
         self.ip = ip.try_into()?;
         Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Frame {
     call_loc: CodeLocation,
     stack_depth: usize,
+    /// How many values the caller unpacks the result into, if it does.
+    unpack: Option<u16>,
 }
 
 pub struct VirtualMachine {
     globals: HashMap<String, Value>,
+    /// Globals which can't be assigned to, only declared again.
+    protected: HashSet<String>,
     stack: Vec<Value>,
     ret_channel: Option<Value>,
     frames: Vec<Frame>,
+    /// Stack index of the first local of the current frame.
+    frame_base: usize,
     loc: CodeLocation,
+    limits: Limits,
+    /// The number of instructions executed so far.
+    steps: u64,
+    /// Set from outside the VM to stop the script it's running.
+    interrupt: Option<&'static AtomicBool>,
+    /// Functions to call whenever their flag gets set from outside the VM.
+    handlers: Vec<(&'static AtomicBool, Value)>,
+    hooks: Hooks,
+    /// Whether numbers with a fractional part are truncated when they're used as an index,
+    /// rather than being an error.
+    allow_float_index: bool,
 }
 
 impl VirtualMachine {
     pub fn new(chunk: Chunk) -> Self {
         VirtualMachine {
             globals: HashMap::new(),
+            protected: HashSet::new(),
             stack: vec![Value::Null],
             ret_channel: None,
             frames: Vec::new(),
-            loc: CodeLocation::new(chunk),
+            frame_base: 0,
+            loc: CodeLocation::new(Rc::new(chunk)),
+            limits: Limits::default(),
+            steps: 0,
+            interrupt: None,
+            handlers: Vec::new(),
+            hooks: Hooks::default(),
+            allow_float_index: false,
+        }
+    }
+
+    /// Stop running with an error whenever `flag` is set, clearing it again.
+    pub fn set_interrupt(&mut self, flag: &'static AtomicBool) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Call `handler` with no arguments whenever `flag` is set, clearing it again,
+    /// in place of the handler it had before.
+    pub fn on_flag(&mut self, flag: &'static AtomicBool, handler: Value) {
+        self.handlers.retain(|(f, _)| !std::ptr::eq(*f, flag));
+        self.handlers.push((flag, handler));
+    }
+
+    /// Call the handlers of any flags set since they were last checked.
+    fn run_handlers(&mut self) -> Result<()> {
+        for i in 0..self.handlers.len() {
+            let (flag, handler) = &self.handlers[i];
+            if flag.swap(false, AtomicOrdering::Relaxed) {
+                // Handlers can run between any two instructions, even while a value is set aside.
+                let saved = self.ret_channel.take();
+                self.call(handler.clone(), Vec::new())?;
+                self.ret_channel = saved;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Recover from an error by leaving every function call,
+    /// and dropping the values pushed on the stack past `depth`.
+    pub fn unwind(&mut self, depth: usize) {
+        while self.pop_frame().is_some() {}
+        self.stack.truncate(depth);
+        self.ret_channel = None;
+    }
+
+    /// Copy the state of the VM, to go back to it later with `restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::take(self)
+    }
+
+    /// Go back to the state the VM was in when `snapshot` was taken.
+    /// The VM's limits stay the way they are.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        snapshot.restore(self);
+    }
+
+    /// Store new versions of some global functions, replacing every reference
+    /// to the versions they used to hold on the stack and in arrays and maps as well.
+    /// Calls to the old versions which are already running carry on with the old code.
+    pub fn redefine(&mut self, functions: Vec<(String, Value)>) {
+        let mut replacements = Vec::new();
+        for (name, new) in functions {
+            if let Some(Value::Function { chunk, .. }) = self.globals.get(&name) {
+                replacements.push((chunk.clone(), new.clone()));
+            }
+            self.globals.insert(name, new);
+        }
+        let mut replacer = reload::Replacer::new(replacements);
+        let values = self
+            .globals
+            .values_mut()
+            .chain(self.stack.iter_mut())
+            .chain(self.ret_channel.iter_mut());
+        for val in values {
+            replacer.replace(val);
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Replace the callbacks the VM runs as it goes, with `hooks`.
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    /// Truncate numbers with a fractional part used as an index, instead of failing.
+    pub fn allow_float_index(&mut self, allow: bool) {
+        self.allow_float_index = allow;
+    }
+
+    /// Check that `x` is a whole number, or truncate it if fractional indices are allowed.
+    pub fn whole(&self, x: f64) -> Result<f64> {
+        Ok(value::whole(x, self.allow_float_index)?)
+    }
+
+    /// Convert a value to an index into an array or string, as the `[]` operator does.
+    pub fn as_index(&self, val: &Value) -> Result<usize> {
+        Ok(value::as_index(val, self.allow_float_index)?)
+    }
+
+    /// Replace an element of an array or the value of a key in a map, as assigning to
+    /// `target[i]` does.
+    pub fn set_index(&self, target: &Value, i: &Value, val: Value) -> Result<()> {
+        Ok(target.set_index(i, val, self.allow_float_index)?)
+    }
+
+    /// Fail if the script was interrupted, has run for too long or allocated too much memory.
+    fn check_limits(&self) -> Result<()> {
+        if let Some(flag) = self.interrupt {
+            if flag.swap(false, AtomicOrdering::Relaxed) {
+                return Err(Error::Interrupted);
+            }
+        }
+        match self.limits {
+            Limits {
+                max_steps: Some(max),
+                ..
+            } if self.steps > max => Err(Error::Limit(Limit::Steps(max))),
+            Limits {
+                max_heap: Some(max),
+                ..
+            } if limits::allocated() > max => Err(Error::Limit(Limit::Heap(max))),
+            _ => Ok(()),
         }
     }
 
@@ -99,20 +360,283 @@ impl VirtualMachine {
         self.globals.insert(name, val);
     }
 
-    fn local_idx(&mut self, offset: u16) -> usize {
-        let frame_idx = self.frames.last().map(|f| f.stack_depth).unwrap_or(0);
-        usize::from(offset) + frame_idx
+    /// Stop scripts from assigning to every global defined so far,
+    /// unless they declare it again with `global` or `fn`.
+    pub fn protect_globals(&mut self) {
+        self.protected.extend(self.globals.keys().cloned());
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /// Store a global assigned or declared by the script.
+    fn write_global(&mut self, name: String, val: Value) -> Result<()> {
+        if let Some(hook) = &mut self.hooks.on_global_write {
+            hook(&name, &val)?;
+        }
+        self.globals.insert(name, val);
+        Ok(())
+    }
+
+    fn undeclared(&self, name: &str) -> Error {
+        let names = self.globals.keys().map(String::as_str);
+        Error::UndeclaredGlobal {
+            name: name.to_owned(),
+            suggestion: suggest::closest(name, names).map(str::to_owned),
+        }
+    }
+
+    fn local_idx(&mut self, offset: u32) -> usize {
+        offset as usize + self.frame_base
+    }
+
+    fn push_frame(&mut self, frame: Frame) {
+        self.frame_base = frame.stack_depth;
+        self.frames.push(frame);
+    }
+
+    fn pop_frame(&mut self) -> Option<Frame> {
+        let frame = self.frames.pop();
+        self.frame_base = self.frames.last().map(|f| f.stack_depth).unwrap_or(0);
+        frame
+    }
+
+    /// Call the function below the `argc` arguments on top of the stack,
+    /// replacing them with its result, or with the `unpack` values it consists of.
+    fn call_value(&mut self, argc: u16, unpack: Option<u16>) -> Result<()> {
+        let argn = usize::from(argc);
+        let index = self.stack.len() - argn - 1;
+        let callable = &self.stack[index];
+        match callable {
+            Value::Function { chunk } => {
+                if let Some(max) = self
+                    .limits
+                    .max_depth
+                    .filter(|max| self.frames.len() >= *max)
+                {
+                    return Err(Error::Limit(Limit::Depth(max)));
+                }
+                let arity = chunk.arity;
+                if argn == arity {
+                    let frame = Frame {
+                        call_loc: self.loc.clone(),
+                        stack_depth: self.stack.len() - arity - 1,
+                        unpack,
+                    };
+                    let chunk = chunk.clone();
+                    if let Some(hook) = &mut self.hooks.on_call {
+                        hook(&self.stack[index], &self.stack[index + 1..])?;
+                    }
+                    let locals = chunk.frame_size - arity - 1;
+                    self.stack.reserve(locals);
+                    self.push_frame(frame);
+                    self.loc = CodeLocation::new(chunk);
+                    Ok(())
+                } else {
+                    Err(Error::WrongArgCount {
+                        expected: Arity::from(arity),
+                        found: argc,
+                    })
+                }
+            }
+            Value::NativeFn { arity, .. } if !arity.accepts(argn) => Err(Error::WrongArgCount {
+                expected: *arity,
+                found: argc,
+            }),
+            Value::NativeFn { f, .. } => {
+                let f = f.clone();
+                let begin = self.stack.len() - argn;
+                let args = self.stack.split_off(begin);
+                let native = self.pop()?;
+                if let Some(hook) = &mut self.hooks.on_call {
+                    hook(&native, &args)?;
+                }
+                let result = f(self, &args)?;
+                if let Some(hook) = &mut self.hooks.on_return {
+                    hook(&result)?;
+                }
+                match unpack {
+                    Some(len) => self.unpack(result, len),
+                    None => {
+                        self.stack.push(result);
+                        Ok(())
+                    }
+                }
+            }
+            _ => Err(Error::Value(value::Error::WrongCall(callable.clone()))),
+        }
+    }
+
+    /// The functions currently being executed, from the innermost outwards.
+    pub fn backtrace(&self) -> Vec<String> {
+        let mut trace: Vec<_> = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| match &self.stack[frame.stack_depth] {
+                Value::Function { chunk } => chunk.to_string(),
+                val => val.to_string(),
+            })
+            .collect();
+        trace.push("<script>".to_owned());
+        trace
+    }
+
+    /// Return `val` from the function currently executing,
+    /// discarding whatever it still had on the stack.
+    fn return_value(&mut self, val: Value) -> Result<()> {
+        if let Some(hook) = &mut self.hooks.on_return {
+            hook(&val)?;
+        }
+        let frame = self.pop_frame().ok_or(Error::EmptyStack)?;
+        self.stack.truncate(frame.stack_depth);
+        self.loc = frame.call_loc;
+        match frame.unpack {
+            Some(len) => self.unpack(val, len),
+            None => {
+                self.stack.push(val);
+                Ok(())
+            }
+        }
+    }
+
+    /// Return the `len` values on top of the stack from the function currently executing.
+    /// They're only put in an array if the caller doesn't unpack them.
+    fn return_values(&mut self, len: u16) -> Result<()> {
+        let begin = self.stack.len() - usize::from(len);
+        let vals = self.stack.split_off(begin);
+        match self.frames.last().ok_or(Error::EmptyStack)?.unpack {
+            // Unless there's a hook to show them to, the values can go straight to the caller.
+            Some(expected) if expected == len && self.hooks.on_return.is_none() => {
+                let frame = self.pop_frame().ok_or(Error::EmptyStack)?;
+                self.stack.truncate(frame.stack_depth);
+                self.stack.extend(vals);
+                self.loc = frame.call_loc;
+                Ok(())
+            }
+            _ => self.return_value(Value::new_array(vals)),
+        }
+    }
+
+    /// Push the elements of the array `val`, which must number exactly `len`.
+    fn unpack(&mut self, val: Value, len: u16) -> Result<()> {
+        let vals = match val {
+            Value::Array(a) => a.borrow().clone(),
+            val => {
+                return Err(Error::Value(value::Error::WrongType {
+                    expected: "Array",
+                    found: val,
+                }))
+            }
+        };
+        if vals.len() != usize::from(len) {
+            return Err(Error::WrongUnpackCount {
+                expected: len,
+                found: vals.len(),
+            });
+        }
+        self.stack.extend(vals);
+        Ok(())
+    }
+
+    /// Call `callee` with the given arguments and run it to completion,
+    /// returning the value it evaluates to.
+    pub fn call(&mut self, callee: Value, args: Vec<Value>) -> Result<Value> {
+        let depth = self.frames.len();
+        let base = self.stack.len();
+        let loc = self.loc.clone();
+        let argc = args.len().try_into()?;
+        self.stack.push(callee);
+        self.stack.extend(args);
+        let result = self.call_value(argc, None).and_then(|()| {
+            while self.frames.len() > depth {
+                self.step()?;
+            }
+            self.pop()
+        });
+        if result.is_err() {
+            // Unwind whatever the failed call left behind.
+            while self.frames.len() > depth {
+                self.pop_frame();
+            }
+            self.stack.truncate(base);
+            self.loc = loc;
+        }
+        result
     }
 
     fn step(&mut self) -> Result<()> {
-        let opcode = self.loc.chunk[self.loc.ip].clone();
+        self.steps += 1;
+        self.check_limits()?;
+        if !self.handlers.is_empty() {
+            self.run_handlers()?;
+        }
+        if let Some((interval, hook)) = &mut self.hooks.on_instruction {
+            if *interval > 0 && self.steps.is_multiple_of(*interval) {
+                hook(self.steps)?;
+            }
+        }
+        let opcode = self.loc.chunk.code[self.loc.ip].clone();
         self.loc.ip += 1;
+        if cfg!(debug_assertions) {
+            self.checked_execute(opcode)
+        } else {
+            self.execute(opcode)
+        }
+    }
+
+    /// Execute an instruction, panicking if it leaves the stack in a different state
+    /// than the compiler expects it to. This catches bugs in the generated code
+    /// right where they happen, rather than once they've corrupted some later result.
+    fn checked_execute(&mut self, opcode: Instruction) -> Result<()> {
+        let (height, depth) = (self.stack.len(), self.frames.len());
+        if let (Instruction::Ret | Instruction::RetMany(_), Some(frame)) =
+            (&opcode, self.frames.last())
+        {
+            // Leaving a function, which may still have locals and temporaries on the stack
+            // when returning early, but must at least have its result.
+            assert!(
+                height > frame.stack_depth,
+                "Function returned without leaving a result on the stack"
+            );
+        }
+        let effect = opcode.stack_effect();
+        let ip = self.loc.ip - 1;
+        self.execute(opcode)?;
+        if let (Some(effect), true) = (effect, self.frames.len() == depth) {
+            // Without entering or leaving a function, the code being run is still the same.
+            assert_eq!(
+                self.stack.len() as isize - height as isize,
+                effect,
+                "{:?} at {} changed the stack by the wrong amount",
+                self.loc.chunk.code[ip],
+                ip,
+            );
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, opcode: Instruction) -> Result<()> {
         match opcode {
             Instruction::Push(val) => {
                 self.stack.push(val);
                 Ok(())
             }
             Instruction::Pop => self.pop().map(|_| ()),
+            Instruction::Dup => {
+                let top = self.peek()?;
+                self.stack.push(top);
+                Ok(())
+            }
+            Instruction::Swap => {
+                let len = self.stack.len();
+                if len < 2 {
+                    return Err(Error::EmptyStack);
+                }
+                self.stack.swap(len - 1, len - 2);
+                Ok(())
+            }
             Instruction::SaveReturn => {
                 let top = self.pop()?;
                 self.ret_channel.replace(top);
@@ -120,7 +644,7 @@ impl VirtualMachine {
             }
             Instruction::RestoreReturn => {
                 let ret = self.ret_channel.take();
-                let ret_val = ret.ok_or_else(|| Error::NoReturnValue)?;
+                let ret_val = ret.ok_or(Error::NoReturnValue)?;
                 self.stack.push(ret_val);
                 Ok(())
             }
@@ -129,14 +653,21 @@ impl VirtualMachine {
                     .globals
                     .get(&name)
                     .cloned()
-                    .ok_or_else(|| Error::UndeclaredGlobal(name.clone()))?;
+                    .ok_or_else(|| self.undeclared(&name))?;
                 self.stack.push(val);
                 Ok(())
             }
             Instruction::SetGlobal(name) => {
+                if self.protected.contains(&name) {
+                    return Err(Error::ProtectedGlobal(name));
+                }
                 let val = self.peek()?;
-                self.globals.insert(name, val);
-                Ok(())
+                self.write_global(name, val)
+            }
+            Instruction::DefineGlobal(name) => {
+                let val = self.peek()?;
+                self.protected.remove(&name);
+                self.write_global(name, val)
             }
             Instruction::GetLocal(idx) => {
                 let idx = self.local_idx(idx);
@@ -169,43 +700,82 @@ impl VirtualMachine {
                 }
                 Ok(())
             }
-            Instruction::Call(argc) => {
-                let argn = usize::from(argc);
-                let index = self.stack.len() - argn - 1;
-                let callable = &self.stack[index];
-                match callable {
-                    Value::Function { chunk, arity, .. } => {
-                        if &argn == arity {
-                            let frame = Frame {
-                                call_loc: self.loc.clone(),
-                                stack_depth: self.stack.len() - arity - 1,
-                            };
-                            self.frames.push(frame);
-                            self.loc = CodeLocation::new(chunk.clone());
-                            Ok(())
-                        } else {
-                            Err(Error::WrongArgCount {
-                                expected: *arity,
-                                found: argc,
-                            })
-                        }
+            Instruction::Call(argc) => self.call_value(argc, None),
+            Instruction::CallUnpack(argc, len) => self.call_value(argc, Some(len)),
+            Instruction::Ret => {
+                let val = self.pop()?;
+                self.return_value(val)
+            }
+            Instruction::RetMany(len) => self.return_values(len),
+            Instruction::Try => match self.pop()? {
+                Value::Ok(val) => {
+                    self.stack.push((*val).clone());
+                    Ok(())
+                }
+                val @ Value::Err(_) | val @ Value::Null if self.frames.is_empty() => {
+                    Err(Error::Propagated(val))
+                }
+                val @ Value::Err(_) | val @ Value::Null => self.return_value(val),
+                val => {
+                    self.stack.push(val);
+                    Ok(())
+                }
+            },
+            Instruction::MakeArray(len) => {
+                let begin = self.stack.len() - usize::from(len);
+                let vals = self.stack.split_off(begin);
+                self.stack.push(Value::new_array(vals));
+                Ok(())
+            }
+            Instruction::IntoIter => {
+                let vals = match self.pop()? {
+                    arr @ Value::Array(_) => arr,
+                    Value::Str(s) => {
+                        let chars = s.chars().map(|c| Value::new_str(c.to_string()));
+                        Value::new_array(chars.collect())
                     }
-                    Value::NativeFn { f, arity } => {
-                        let begin = self.stack.len() - arity;
-                        let result = f(&self.stack[begin..])?;
-                        self.stack.drain(begin..);
-                        self.stack.pop(); // Function object
-                        self.stack.push(result);
-                        Ok(())
+                    Value::Map(m) => {
+                        let keys = m.borrow().keys().map(|key| key.value().clone()).collect();
+                        Value::new_array(keys)
                     }
-                    _ => Err(Error::Value(value::Error::WrongCall(callable.clone()))),
-                }
+                    Value::Set(s) => {
+                        let vals = s.borrow().iter().map(|key| key.value().clone()).collect();
+                        Value::new_array(vals)
+                    }
+                    val => {
+                        return Err(Error::Value(value::Error::WrongType {
+                            expected: "Array, Str, Map or Set",
+                            found: val,
+                        }))
+                    }
+                };
+                self.stack.push(vals);
+                Ok(())
             }
-            Instruction::Ret => {
-                let frame = self.frames.pop().ok_or(Error::EmptyStack)?;
-                self.loc = frame.call_loc;
+            Instruction::Next(idx) => {
+                let idx = self.local_idx(idx);
+                let pos = match &self.stack[idx + 1] {
+                    Value::Num(pos) => *pos as usize,
+                    _ => unreachable!("for loop positions are always numbers"),
+                };
+                let next = match &self.stack[idx] {
+                    Value::Array(a) => a.borrow().get(pos).cloned(),
+                    _ => unreachable!("for loops always go through arrays"),
+                };
+                if let Some(val) = next {
+                    self.stack[idx + 1] = Value::Num((pos + 1) as f64);
+                    self.stack.push(val);
+                    self.stack.push(Value::Bool(true));
+                } else {
+                    self.stack.push(Value::Null);
+                    self.stack.push(Value::Bool(false));
+                }
                 Ok(())
             }
+            Instruction::Unpack(len) => {
+                let val = self.pop()?;
+                self.unpack(val, len)
+            }
             Instruction::Add => {
                 let b = self.pop()?;
                 let a = self.pop()?;
@@ -220,6 +790,35 @@ impl VirtualMachine {
                 self.stack.push(result);
                 Ok(())
             }
+            Instruction::AddNum => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                // The compiler has proven these to be numbers, but check rather than trust it.
+                let result = match (&a, &b) {
+                    (Value::Num(a), Value::Num(b)) => Value::Num(a + b),
+                    (Value::Int(x), Value::Int(y)) => match x.checked_add(*y) {
+                        Some(sum) => Value::Int(sum),
+                        None => (a + b)?,
+                    },
+                    _ => (a + b)?,
+                };
+                self.stack.push(result);
+                Ok(())
+            }
+            Instruction::SubNum => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = match (&a, &b) {
+                    (Value::Num(a), Value::Num(b)) => Value::Num(a - b),
+                    (Value::Int(x), Value::Int(y)) => match x.checked_sub(*y) {
+                        Some(difference) => Value::Int(difference),
+                        None => (a - b)?,
+                    },
+                    _ => (a - b)?,
+                };
+                self.stack.push(result);
+                Ok(())
+            }
             Instruction::Mul => {
                 let b = self.pop()?;
                 let a = self.pop()?;
@@ -234,6 +833,20 @@ impl VirtualMachine {
                 self.stack.push(result);
                 Ok(())
             }
+            Instruction::BitOr => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = (a | b)?;
+                self.stack.push(result);
+                Ok(())
+            }
+            Instruction::BitAnd => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = (a & b)?;
+                self.stack.push(result);
+                Ok(())
+            }
             Instruction::Neg => {
                 let a = self.pop()?;
                 let result = (-a)?;
@@ -245,31 +858,80 @@ impl VirtualMachine {
                 self.stack.push(!a);
                 Ok(())
             }
+            Instruction::Index => {
+                let i = self.pop()?;
+                let val = self.pop()?;
+                self.stack.push(val.index(&i, self.allow_float_index)?);
+                Ok(())
+            }
+            Instruction::SetIndex => {
+                let val = self.pop()?;
+                let i = self.pop()?;
+                let target = self.pop()?;
+                self.set_index(&target, &i, val.clone())?;
+                self.stack.push(val);
+                Ok(())
+            }
+            Instruction::ImportAll => {
+                let entries = match self.pop()? {
+                    Value::Map(m) => m.borrow().clone(),
+                    val => {
+                        return Err(Error::Value(value::Error::WrongType {
+                            expected: "Map",
+                            found: val,
+                        }))
+                    }
+                };
+                for (key, val) in entries {
+                    let name = match key.value() {
+                        Value::Str(s) => s.to_string(),
+                        val => {
+                            return Err(Error::Value(value::Error::WrongType {
+                                expected: "Str",
+                                found: val.clone(),
+                            }))
+                        }
+                    };
+                    self.protected.remove(&name);
+                    self.write_global(name, val)?;
+                }
+                self.stack.push(Value::Null);
+                Ok(())
+            }
             Instruction::Equal => {
                 let b = self.pop()?;
                 let a = self.pop()?;
                 self.stack.push(Value::Bool(a == b));
                 Ok(())
             }
+            Instruction::Is => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push(Value::Bool(a.is(&b)));
+                Ok(())
+            }
             Instruction::Less => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = if let Ordering::Less = a.cmp(&b)? {
-                    true
-                } else {
-                    false
-                };
+                let result = matches!(a.cmp(&b)?, Ordering::Less);
                 self.stack.push(Value::Bool(result));
                 Ok(())
             }
-            Instruction::Greater => {
+            Instruction::LessNum => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = if let Ordering::Greater = a.cmp(&b)? {
-                    true
-                } else {
-                    false
+                let result = match (&a, &b) {
+                    (Value::Num(a), Value::Num(b)) => Value::Bool(a < b),
+                    (Value::Int(a), Value::Int(b)) => Value::Bool(a < b),
+                    _ => Value::Bool(matches!(a.cmp(&b)?, Ordering::Less)),
                 };
+                self.stack.push(result);
+                Ok(())
+            }
+            Instruction::Greater => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = matches!(a.cmp(&b)?, Ordering::Greater);
                 self.stack.push(Value::Bool(result));
                 Ok(())
             }
@@ -289,7 +951,7 @@ impl VirtualMachine {
     }
 
     pub fn change_chunk(&mut self, chunk: Chunk) {
-        self.loc = CodeLocation::new(chunk);
+        self.loc = CodeLocation::new(Rc::new(chunk));
     }
 }
 
@@ -299,10 +961,36 @@ pub type ValueError = value::Error;
 pub enum Error {
     Value(ValueError),
     Conversion(TryFromIntError),
-    UndeclaredGlobal(String),
-    WrongArgCount { expected: usize, found: u16 },
+    IO(io::Error),
+    UndeclaredGlobal {
+        name: String,
+        /// A global with a similar name, which might have been meant instead.
+        suggestion: Option<String>,
+    },
+    WrongArgCount {
+        expected: Arity,
+        found: u16,
+    },
+    /// An assignment to a global of the standard library, which wasn't declared again.
+    ProtectedGlobal(String),
+    /// An array being unpacked into variables had a different number of elements.
+    WrongUnpackCount {
+        expected: u16,
+        found: usize,
+    },
     EmptyStack,
     NoReturnValue,
+    /// An error or null value propagated with `?` outside of any function.
+    Propagated(Value),
+    /// The script was stopped from outside the VM.
+    Interrupted,
+    /// A resource limit the script went past.
+    Limit(Limit),
+    /// A call to `panic`, along with the functions that were running at the time.
+    Panic {
+        message: String,
+        backtrace: Vec<String>,
+    },
 }
 
 impl From<ValueError> for Error {
@@ -311,6 +999,12 @@ impl From<ValueError> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::IO(err)
+    }
+}
+
 impl From<TryFromIntError> for Error {
     fn from(err: TryFromIntError) -> Self {
         Error::Conversion(err)
@@ -322,7 +1016,22 @@ impl Display for Error {
         match self {
             Error::Value(err) => write!(f, "{}", err),
             Error::Conversion(err) => write!(f, "Number too big to fit into VM code: {}", err),
-            Error::UndeclaredGlobal(name) => write!(f, "Nonexistent variable '{}'", name),
+            Error::IO(err) => write!(f, "{}", err),
+            Error::UndeclaredGlobal { name, suggestion } => {
+                write!(f, "Nonexistent variable '{}'", name)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ", did you mean '{}'?", suggestion)?;
+                }
+                Ok(())
+            }
+            Error::ProtectedGlobal(name) => write!(
+                f,
+                "Cannot assign to '{}' from the standard library, declare it with `global` to replace it",
+                name
+            ),
+            Error::WrongUnpackCount { expected, found } => {
+                write!(f, "Expected {} values to unpack, found {}", expected, found)
+            }
             Error::WrongArgCount { expected, found } => write!(
                 f,
                 "Wrong argument count to function call: expected {}, found {}",
@@ -330,6 +1039,17 @@ impl Display for Error {
             ),
             Error::EmptyStack => write!(f, "Cannot return value out of an empty stack"),
             Error::NoReturnValue => write!(f, "Tried restoring value from empty return channel"),
+            Error::Propagated(Value::Err(err)) => write!(f, "Unhandled error: {}", err),
+            Error::Propagated(val) => write!(f, "Unhandled {} value", val),
+            Error::Interrupted => write!(f, "Interrupted"),
+            Error::Limit(limit) => write!(f, "{}", limit),
+            Error::Panic { message, backtrace } => {
+                write!(f, "panic: {}", message)?;
+                for function in backtrace {
+                    write!(f, "\n    in {}", function)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -339,6 +1059,7 @@ impl std::error::Error for Error {
         match self {
             Error::Value(err) => Some(err),
             Error::Conversion(err) => Some(err),
+            Error::IO(err) => Some(err),
             _ => None,
         }
     }
@@ -0,0 +1,240 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use num_bigint::BigInt;
+use num_traits::{One as _, Pow as _, Signed as _, Zero as _};
+
+/// Number of decimal places kept by divisions which don't terminate.
+const DIVISION_PLACES: u32 = 28;
+
+/// An exact decimal number, represented as `mantissa / 10^scale`.
+#[derive(Clone, Debug)]
+pub struct Decimal {
+    mantissa: BigInt,
+    scale: u32,
+}
+
+/// How to get rid of the digits which don't fit when rounding a decimal.
+#[derive(Clone, Copy, Debug)]
+pub enum Rounding {
+    HalfEven,
+    HalfUp,
+    Down,
+    Up,
+    Floor,
+    Ceiling,
+}
+
+fn pow10(exp: u32) -> BigInt {
+    BigInt::from(10u32).pow(exp)
+}
+
+impl Decimal {
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    /// The mantissa of this number when written with `scale` decimal places,
+    /// which must be at least as many as it currently has.
+    fn mantissa_at(&self, scale: u32) -> BigInt {
+        &self.mantissa * pow10(scale - self.scale)
+    }
+
+    fn aligned(&self, other: &Decimal) -> (BigInt, BigInt, u32) {
+        let scale = self.scale.max(other.scale);
+        (self.mantissa_at(scale), other.mantissa_at(scale), scale)
+    }
+
+    /// The same number without trailing zeros after the decimal point.
+    fn normalized(&self) -> Decimal {
+        let ten = BigInt::from(10u32);
+        let mut mantissa = self.mantissa.clone();
+        let mut scale = self.scale;
+        while scale > 0 && (&mantissa % &ten).is_zero() {
+            mantissa /= &ten;
+            scale -= 1;
+        }
+        Decimal { mantissa, scale }
+    }
+
+    pub fn add(&self, other: &Decimal) -> Decimal {
+        let (a, b, scale) = self.aligned(other);
+        Decimal {
+            mantissa: a + b,
+            scale,
+        }
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Decimal {
+        let (a, b, scale) = self.aligned(other);
+        Decimal {
+            mantissa: a - b,
+            scale,
+        }
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Decimal {
+        Decimal {
+            mantissa: &self.mantissa * &other.mantissa,
+            scale: self.scale + other.scale,
+        }
+    }
+
+    /// Divide two decimals, rounding the result half-to-even if it doesn't terminate
+    /// within `DIVISION_PLACES` digits. Returns `None` when dividing by zero.
+    pub fn div(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        let num = &self.mantissa * pow10(other.scale + DIVISION_PLACES);
+        let den = &other.mantissa * pow10(self.scale);
+        let mantissa = round_quotient(&num, &den, Rounding::HalfEven);
+        let result = Decimal {
+            mantissa,
+            scale: DIVISION_PLACES,
+        };
+        Some(result.normalized())
+    }
+
+    pub fn neg(&self) -> Decimal {
+        Decimal {
+            mantissa: -&self.mantissa,
+            scale: self.scale,
+        }
+    }
+
+    /// Round this number to `places` decimal places.
+    pub fn round(&self, places: u32, mode: Rounding) -> Decimal {
+        if places >= self.scale {
+            return Decimal {
+                mantissa: self.mantissa_at(places),
+                scale: places,
+            };
+        }
+        let mantissa = round_quotient(&self.mantissa, &pow10(self.scale - places), mode);
+        Decimal {
+            mantissa,
+            scale: places,
+        }
+    }
+}
+
+/// Divide `num` by `den`, rounding the quotient to an integer according to `mode`.
+fn round_quotient(num: &BigInt, den: &BigInt, mode: Rounding) -> BigInt {
+    let quot = num / den;
+    let rem = num % den;
+    if rem.is_zero() {
+        return quot;
+    }
+    let negative = num.is_negative() != den.is_negative();
+    let twice_rem: BigInt = rem.abs() * 2u32;
+    let den = den.abs();
+    let away_from_zero = match mode {
+        Rounding::Down => false,
+        Rounding::Up => true,
+        Rounding::Floor => negative,
+        Rounding::Ceiling => !negative,
+        Rounding::HalfUp => twice_rem >= den,
+        Rounding::HalfEven => match twice_rem.cmp(&den) {
+            Ordering::Less => false,
+            Ordering::Equal => (&quot % 2u32) != BigInt::zero(),
+            Ordering::Greater => true,
+        },
+    };
+    if !away_from_zero {
+        quot
+    } else if negative {
+        quot - BigInt::one()
+    } else {
+        quot + BigInt::one()
+    }
+}
+
+impl From<BigInt> for Decimal {
+    fn from(mantissa: BigInt) -> Self {
+        Decimal { mantissa, scale: 0 }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ();
+
+    /// Parse numbers of the form `[+-]digits[.digits]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if int.len() + frac.len() == 0 || !all_digits(int) || !all_digits(frac) {
+            return Err(());
+        }
+        let mantissa: BigInt = format!("{}{}", int, frac).parse().map_err(|_| ())?;
+        Ok(Decimal {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale: frac.len() as u32,
+        })
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half_even" => Ok(Rounding::HalfEven),
+            "half_up" => Ok(Rounding::HalfUp),
+            "down" => Ok(Rounding::Down),
+            "up" => Ok(Rounding::Up),
+            "floor" => Ok(Rounding::Floor),
+            "ceiling" => Ok(Rounding::Ceiling),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.scale == 0 {
+            return write!(f, "{}", self.mantissa);
+        }
+        let scale = self.scale as usize;
+        let digits = format!("{:0>width$}", self.mantissa.abs(), width = scale + 1);
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        let sign = if self.mantissa.is_negative() { "-" } else { "" };
+        write!(f, "{}{}.{}", sign, int, frac)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b, _) = self.aligned(other);
+        a == b
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b, _) = self.aligned(other);
+        a.cmp(&b)
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let normalized = self.normalized();
+        normalized.mantissa.hash(state);
+        normalized.scale.hash(state);
+    }
+}
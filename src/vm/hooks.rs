@@ -0,0 +1,21 @@
+use crate::vm::{Result, Value};
+
+type CallHook = Box<dyn FnMut(&Value, &[Value]) -> Result<()>>;
+type ReturnHook = Box<dyn FnMut(&Value) -> Result<()>>;
+type StepHook = Box<dyn FnMut(u64) -> Result<()>>;
+type GlobalHook = Box<dyn FnMut(&str, &Value) -> Result<()>>;
+
+/// Callbacks through which a host can watch a VM run, to profile, audit or stop scripts
+/// without changing the VM itself. Any of them can stop the script by returning an error.
+#[derive(Default)]
+pub struct Hooks {
+    /// Called with a function and its arguments, right before it's run.
+    pub on_call: Option<CallHook>,
+    /// Called with the value a function returns, right after it does.
+    pub on_return: Option<ReturnHook>,
+    /// Called every so many instructions, given first (or never, if it's zero),
+    /// with the number of instructions run so far.
+    pub on_instruction: Option<(u64, StepHook)>,
+    /// Called with the name and new value of a global, whenever one is assigned or declared.
+    pub on_global_write: Option<GlobalHook>,
+}
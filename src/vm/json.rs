@@ -0,0 +1,282 @@
+use std::fmt::{self, Display, Write as _};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use indexmap::IndexMap;
+
+use crate::vm::{Key, Value};
+
+#[derive(Debug)]
+pub enum Error {
+    /// A value which has no JSON representation.
+    Unsupported(&'static str),
+    /// Malformed JSON text, and the byte offset the problem was found at.
+    Syntax { message: String, offset: usize },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Unsupported(type_name) => {
+                write!(f, "Values of type '{}' can't be stored as JSON", type_name)
+            }
+            Error::Syntax { message, offset } => {
+                write!(f, "Invalid JSON at offset {}: {}", offset, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Convert `val` to JSON text. Only null, finite numbers, booleans, strings,
+/// arrays and maps with string keys can be converted.
+pub fn encode(val: &Value) -> Result<String> {
+    let mut out = String::new();
+    write_value(&mut out, val)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut String, val: &Value) -> Result<()> {
+    match val {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => write!(out, "{}", b).unwrap(),
+        Value::Num(x) if x.is_finite() => write!(out, "{}", x).unwrap(),
+        Value::Int(x) => write!(out, "{}", x).unwrap(),
+        Value::Str(s) => write_str(out, s),
+        Value::Array(a) => {
+            out.push('[');
+            for (i, val) in a.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, val)?;
+            }
+            out.push(']');
+        }
+        Value::Map(m) => {
+            out.push('{');
+            for (i, (key, val)) in m.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                match key.value() {
+                    Value::Str(s) => write_str(out, s),
+                    key => return Err(Error::Unsupported(key.type_name())),
+                }
+                out.push(':');
+                write_value(out, val)?;
+            }
+            out.push('}');
+        }
+        val => return Err(Error::Unsupported(val.type_name())),
+    }
+    Ok(())
+}
+
+fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Parse JSON text into a value, turning objects into maps.
+pub fn decode(text: &str) -> Result<Value> {
+    let mut parser = Parser {
+        chars: text.char_indices().peekable(),
+        len: text.len(),
+    };
+    let val = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(val),
+        Some((offset, _)) => Err(parser.error(offset, "unexpected text after the value")),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, offset: usize, message: &str) -> Error {
+        Error::Syntax {
+            message: message.to_owned(),
+            offset,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some((_, ' ')) | Some((_, '\n')) | Some((_, '\r')) | Some((_, '\t')) =
+            self.chars.peek()
+        {
+            self.chars.next();
+        }
+    }
+
+    /// Consume the next character, which must be `expected`.
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((offset, _)) => Err(self.error(offset, &format!("expected '{}'", expected))),
+            None => Err(self.error(self.len, &format!("expected '{}'", expected))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        let (offset, c) = match self.chars.peek() {
+            Some(&next) => next,
+            None => return Err(self.error(self.len, "unexpected end of input")),
+        };
+        match c {
+            'n' => self.word("null", Value::Null),
+            't' => self.word("true", Value::Bool(true)),
+            'f' => self.word("false", Value::Bool(false)),
+            '"' => self.string().map(Value::new_str),
+            '[' => self.array(),
+            '{' => self.object(),
+            '-' | '0'..='9' => self.number(),
+            _ => Err(self.error(offset, "expected a value")),
+        }
+    }
+
+    fn word(&mut self, word: &str, val: Value) -> Result<Value> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(val)
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let mut text = String::new();
+        let offset = self.chars.peek().map_or(self.len, |&(i, _)| i);
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c.is_ascii_digit() || "+-.eE".contains(c) {
+                text.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        // Whole numbers become ints, so that large IDs survive the trip intact.
+        if let Ok(x) = text.parse() {
+            return Ok(Value::Int(x));
+        }
+        text.parse()
+            .map(Value::Num)
+            .map_err(|_| self.error(offset, "invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((offset, '\\')) => {
+                    let c = match self.chars.next() {
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, '/')) => '/',
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'u')) => self.unicode_escape(offset)?,
+                        _ => return Err(self.error(offset, "invalid escape sequence")),
+                    };
+                    s.push(c);
+                }
+                Some((_, c)) => s.push(c),
+                None => return Err(self.error(self.len, "unterminated string")),
+            }
+        }
+    }
+
+    /// Parse the digits of a `\u` escape, including surrogate pairs.
+    fn unicode_escape(&mut self, offset: usize) -> Result<char> {
+        let high = self.hex4(offset)?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            self.expect('\\')?;
+            self.expect('u')?;
+            let low = self.hex4(offset)?;
+            0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error(offset, "invalid unicode escape"))
+    }
+
+    fn hex4(&mut self, offset: usize) -> Result<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or_else(|| self.error(offset, "invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.expect('[')?;
+        let mut vals = Vec::new();
+        self.skip_whitespace();
+        if let Some((_, ']')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Value::new_array(vals));
+        }
+        loop {
+            vals.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, ']')) => return Ok(Value::new_array(vals)),
+                Some((offset, _)) => return Err(self.error(offset, "expected ',' or ']'")),
+                None => return Err(self.error(self.len, "unterminated array")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        let mut entries = IndexMap::new();
+        self.skip_whitespace();
+        if let Some((_, '}')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Value::new_map_from(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = Value::new_str(self.string()?);
+            self.skip_whitespace();
+            self.expect(':')?;
+            let val = self.value()?;
+            entries.insert(Key::new(key).expect("strings are valid keys"), val);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => return Ok(Value::new_map_from(entries)),
+                Some((offset, _)) => return Err(self.error(offset, "expected ',' or '}'")),
+                None => return Err(self.error(self.len, "unterminated object")),
+            }
+        }
+    }
+}
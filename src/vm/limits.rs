@@ -0,0 +1,75 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bounds on the resources a script may use, past which it's stopped with an error.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// The deepest function calls can be nested.
+    pub max_depth: Option<usize>,
+    /// The number of instructions the VM may execute.
+    pub max_steps: Option<u64>,
+    /// The number of bytes the whole process may have allocated at once.
+    pub max_heap: Option<usize>,
+}
+
+/// A limit which was exceeded, along with its value.
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    Depth(usize),
+    Steps(u64),
+    Heap(usize),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Depth(max) => write!(f, "Exceeded the maximum call depth of {}", max),
+            Limit::Steps(max) => write!(f, "Exceeded the maximum of {} steps", max),
+            Limit::Heap(max) => write!(f, "Exceeded the maximum heap size of {} bytes", max),
+        }
+    }
+}
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of bytes currently allocated through `CountingAllocator`.
+pub fn allocated() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+/// The system allocator, keeping count of the bytes allocated through it
+/// so that `max_heap` can be enforced.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::vm::{Chunk, Value};
+
+/// Replaces every reference to some functions held by a set of values,
+/// looking inside arrays and maps for them too.
+pub struct Replacer {
+    /// The code of each replaced function, and the function replacing it.
+    replacements: Vec<(Rc<Chunk>, Value)>,
+    /// The aggregates already searched, so that each is only visited once.
+    seen: HashSet<*const ()>,
+}
+
+impl Replacer {
+    pub fn new(replacements: Vec<(Rc<Chunk>, Value)>) -> Self {
+        Replacer {
+            replacements,
+            seen: HashSet::new(),
+        }
+    }
+
+    fn replacement(&self, val: &Value) -> Option<Value> {
+        match val {
+            Value::Function { chunk, .. } => self
+                .replacements
+                .iter()
+                .find(|(old, _)| Rc::ptr_eq(old, chunk))
+                .map(|(_, new)| new.clone()),
+            _ => None,
+        }
+    }
+
+    /// Replace `val` if it's one of the old functions, or any of them it contains.
+    pub fn replace(&mut self, val: &mut Value) {
+        if let Some(new) = self.replacement(val) {
+            *val = new;
+            return;
+        }
+        let ptr = match val {
+            Value::Array(a) => Rc::as_ptr(a) as *const (),
+            Value::Map(m) => Rc::as_ptr(m) as *const (),
+            _ => return,
+        };
+        if !self.seen.insert(ptr) {
+            return;
+        }
+        // Frozen aggregates keep referring to the old functions, like they keep everything else.
+        match val {
+            Value::Array(a) => {
+                if let Ok(mut vals) = a.borrow_mut() {
+                    vals.iter_mut().for_each(|val| self.replace(val));
+                }
+            }
+            Value::Map(m) => {
+                if let Ok(mut entries) = m.borrow_mut() {
+                    entries.values_mut().for_each(|val| self.replace(val));
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::cell::RefMut;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::vm::value::Shared;
+use crate::vm::{CodeLocation, Frame, Value, VirtualMachine, WeakRef};
+
+/// A copy of everything a VM is keeping track of at some point of running a script,
+/// which it can be restored to later, any number of times.
+///
+/// Host objects are the only values shared with the running VM,
+/// since they can't be copied without knowing what they are.
+pub struct Snapshot {
+    globals: HashMap<String, Value>,
+    protected: HashSet<String>,
+    stack: Vec<Value>,
+    ret_channel: Option<Value>,
+    frames: Vec<Frame>,
+    loc: CodeLocation,
+    steps: u64,
+}
+
+impl Snapshot {
+    pub(super) fn take(vm: &VirtualMachine) -> Self {
+        let mut copier = Copier::default();
+        Snapshot {
+            globals: copier.copy_globals(&vm.globals),
+            protected: vm.protected.clone(),
+            stack: vm.stack.iter().map(|val| copier.copy(val)).collect(),
+            ret_channel: vm.ret_channel.as_ref().map(|val| copier.copy(val)),
+            frames: vm.frames.clone(),
+            loc: vm.loc.clone(),
+            steps: vm.steps,
+        }
+    }
+
+    pub(super) fn restore(&self, vm: &mut VirtualMachine) {
+        // Copy everything again, so that running the VM leaves the snapshot untouched.
+        let mut copier = Copier::default();
+        vm.globals = copier.copy_globals(&self.globals);
+        vm.protected = self.protected.clone();
+        vm.stack = self.stack.iter().map(|val| copier.copy(val)).collect();
+        vm.ret_channel = self.ret_channel.as_ref().map(|val| copier.copy(val));
+        vm.frames = self.frames.clone();
+        vm.frame_base = vm.frames.last().map_or(0, |frame| frame.stack_depth);
+        vm.loc = self.loc.clone();
+        vm.steps = self.steps;
+    }
+}
+
+/// Makes deep copies of values, so that modifying a copy leaves the original alone.
+/// Aggregates referenced more than once are only copied once,
+/// so the copies share them the same way the originals did, cycles included.
+#[derive(Default)]
+struct Copier {
+    copies: HashMap<*const (), Value>,
+}
+
+impl Copier {
+    fn copy_globals(&mut self, globals: &HashMap<String, Value>) -> HashMap<String, Value> {
+        globals
+            .iter()
+            .map(|(name, val)| (name.clone(), self.copy(val)))
+            .collect()
+    }
+
+    fn copy(&mut self, val: &Value) -> Value {
+        let ptr = match val {
+            // Frozen aggregates can't change, so they may as well be shared.
+            Value::Array(a) if !a.is_frozen() => Rc::as_ptr(a) as *const (),
+            Value::Map(m) if !m.is_frozen() => Rc::as_ptr(m) as *const (),
+            Value::Set(s) if !s.is_frozen() => Rc::as_ptr(s) as *const (),
+            Value::Builder(b) => Rc::as_ptr(b) as *const (),
+            Value::Ok(val) => return Value::Ok(Rc::new(self.copy(val))),
+            Value::Err(err) => return Value::Err(Rc::new(self.copy(err))),
+            Value::Weak(weak) => return self.copy_weak(weak),
+            val => return val.clone(),
+        };
+        if let Some(copy) = self.copies.get(&ptr) {
+            return copy.clone();
+        }
+        // Remember the copy before filling it in, in case the value contains itself.
+        let copy = match val {
+            Value::Array(_) => Value::new_array(Vec::new()),
+            Value::Map(_) => Value::new_map(),
+            Value::Set(s) => Value::new_set(s.borrow().clone()),
+            Value::Builder(b) => Value::Builder(Rc::new((**b).clone())),
+            _ => unreachable!(),
+        };
+        self.copies.insert(ptr, copy.clone());
+        match (val, &copy) {
+            (Value::Array(a), Value::Array(copy)) => {
+                let vals = a.borrow().iter().map(|val| self.copy(val)).collect();
+                *borrow_new(copy) = vals;
+            }
+            (Value::Map(m), Value::Map(copy)) => {
+                let entries = m
+                    .borrow()
+                    .iter()
+                    .map(|(key, val)| (key.clone(), self.copy(val)))
+                    .collect();
+                *borrow_new(copy) = entries;
+            }
+            _ => (),
+        }
+        copy
+    }
+
+    /// Point a weak reference at the copy of its referent, if it's still alive.
+    /// If nothing else in the copy refers to it, the new reference is dead straight away,
+    /// just like the original would be once the rest of the VM stopped using it.
+    fn copy_weak(&mut self, weak: &WeakRef) -> Value {
+        match weak.upgrade().map(|val| self.copy(&val)) {
+            Some(copy) => Value::Weak(WeakRef::new(&copy).expect("only aggregates are copied")),
+            None => Value::Weak(weak.clone()),
+        }
+    }
+}
+
+/// Borrow a copy which was only just created, and so isn't frozen or borrowed anywhere else.
+fn borrow_new<T>(shared: &Shared<T>) -> RefMut<'_, T> {
+    shared.borrow_mut().expect("copies are never frozen")
+}
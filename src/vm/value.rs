@@ -1,24 +1,231 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto as _;
 use std::fmt::{self, Debug, Display};
+use std::hash::{Hash, Hasher};
 use std::ops::*;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
-use crate::vm::Chunk;
+use indexmap::{IndexMap, IndexSet};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive as _, ToPrimitive as _, Zero as _};
+
+use crate::vm::decimal::Decimal;
+use crate::vm::{self, Chunk, VirtualMachine};
+
+pub type Native = Rc<dyn Fn(&mut VirtualMachine, &[Value]) -> vm::Result<Value>>;
+pub type Array = Rc<Shared<Vec<Value>>>;
+pub type Map = Rc<Shared<IndexMap<Key, Value>>>;
+pub type Set = Rc<Shared<IndexSet<Key>>>;
+
+/// Check that `x` is a whole number, as indices and counts have to be,
+/// truncating it instead if `allow_float` is set, like older versions did.
+pub fn whole(x: f64, allow_float: bool) -> Result<f64> {
+    if x.fract() == 0.0 {
+        Ok(x)
+    } else if allow_float && x.is_finite() {
+        Ok(x.trunc())
+    } else {
+        Err(Error::NotWhole(x))
+    }
+}
+
+/// Convert a value to an index into an array or string,
+/// truncating fractional numbers if `allow_float` is set.
+pub fn as_index(val: &Value, allow_float: bool) -> Result<usize> {
+    match val {
+        Value::Num(x) if *x >= 0.0 => Ok(whole(*x, allow_float)? as usize),
+        Value::Num(x) => Err(Error::OutOfBounds { index: *x, len: 0 }),
+        Value::Int(x) => (*x).try_into().map_err(|_| Error::OutOfBounds {
+            index: *x as f64,
+            len: 0,
+        }),
+        val => Err(Error::WrongType {
+            expected: "Num",
+            found: val.clone(),
+        }),
+    }
+}
+
+/// The numbers of arguments a native function can be called with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    /// The most arguments the function takes, or None if it takes any number of them.
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn accepts(&self, argc: usize) -> bool {
+        argc >= self.min && self.max.is_none_or(|max| argc <= max)
+    }
+
+    /// The arity left after passing the first `n` arguments, if it takes that many.
+    pub fn after(&self, n: usize) -> Option<Arity> {
+        if self.max.is_some_and(|max| n > max) {
+            return None;
+        }
+        Some(Arity {
+            min: self.min.saturating_sub(n),
+            max: self.max.map(|max| max - n),
+        })
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity {
+            min: n,
+            max: Some(n),
+        }
+    }
+}
+
+impl From<RangeInclusive<usize>> for Arity {
+    fn from(range: RangeInclusive<usize>) -> Self {
+        Arity {
+            min: *range.start(),
+            max: Some(*range.end()),
+        }
+    }
+}
+
+impl From<RangeFrom<usize>> for Arity {
+    fn from(range: RangeFrom<usize>) -> Self {
+        Arity {
+            min: range.start,
+            max: None,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// A reference to an aggregate which doesn't keep it alive.
+#[derive(Clone)]
+pub enum WeakRef {
+    Array(Weak<Shared<Vec<Value>>>),
+    Map(Weak<Shared<IndexMap<Key, Value>>>),
+    Set(Weak<Shared<IndexSet<Key>>>),
+}
+
+impl WeakRef {
+    pub fn new(val: &Value) -> Result<WeakRef> {
+        match val {
+            Value::Array(a) => Ok(WeakRef::Array(Rc::downgrade(a))),
+            Value::Map(m) => Ok(WeakRef::Map(Rc::downgrade(m))),
+            Value::Set(s) => Ok(WeakRef::Set(Rc::downgrade(s))),
+            val => Err(Error::WrongType {
+                expected: "Array, Map or Set",
+                found: val.clone(),
+            }),
+        }
+    }
+
+    /// The referenced value, if it is still alive.
+    pub fn upgrade(&self) -> Option<Value> {
+        match self {
+            WeakRef::Array(a) => a.upgrade().map(Value::Array),
+            WeakRef::Map(m) => m.upgrade().map(Value::Map),
+            WeakRef::Set(s) => s.upgrade().map(Value::Set),
+        }
+    }
+
+    fn ptr_eq(&self, other: &WeakRef) -> bool {
+        match (self, other) {
+            (WeakRef::Array(a), WeakRef::Array(b)) => a.ptr_eq(b),
+            (WeakRef::Map(a), WeakRef::Map(b)) => a.ptr_eq(b),
+            (WeakRef::Set(a), WeakRef::Set(b)) => a.ptr_eq(b),
+            _ => false,
+        }
+    }
+}
+
+/// The contents of an aggregate value, shared by every reference to it.
+/// Frozen aggregates reject any attempt to modify them.
+pub struct Shared<T> {
+    data: RefCell<T>,
+    frozen: bool,
+}
+
+impl<T> Shared<T> {
+    pub fn new(data: T) -> Rc<Self> {
+        Rc::new(Shared {
+            data: RefCell::new(data),
+            frozen: false,
+        })
+    }
+
+    pub fn frozen(data: T) -> Rc<Self> {
+        Rc::new(Shared {
+            data: RefCell::new(data),
+            frozen: true,
+        })
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.data.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> Result<RefMut<'_, T>> {
+        if self.frozen {
+            Err(Error::Frozen)
+        } else {
+            Ok(self.data.borrow_mut())
+        }
+    }
+}
 
 #[derive(Clone)]
 pub enum Value {
     Null,
     Num(f64),
-    Str(String),
+    /// Whole numbers, which stay exact as long as they fit in 64 bits,
+    /// and turn into big integers when arithmetic on them overflows.
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    Decimal(Rc<Decimal>),
+    /// Strings are shared between copies, and only cloned when
+    /// appending to a string that is referenced elsewhere.
+    Str(Rc<String>),
     Bool(bool),
+    /// The result of an operation which succeeded.
+    Ok(Rc<Value>),
+    /// The result of an operation which failed, holding the reason it did.
+    Err(Rc<Value>),
+    Array(Array),
+    Map(Map),
+    Set(Set),
+    Weak(WeakRef),
+    Builder(Rc<RefCell<String>>),
     Function {
-        chunk: Chunk,
-        name: Option<String>,
-        arity: usize,
+        chunk: Rc<Chunk>,
     },
     NativeFn {
-        f: Rc<dyn Fn(&[Value]) -> Result<Value>>,
-        arity: usize,
+        f: Native,
+        arity: Arity,
+        /// The global the native was defined as, if any.
+        /// It's behind a thin pointer so that it doesn't make every value bigger.
+        name: Option<Rc<String>>,
+    },
+    /// An object managed by the host, only usable through natives which know its type.
+    Host {
+        type_name: &'static str,
+        object: Rc<dyn Any>,
     },
 }
 
@@ -27,20 +234,217 @@ impl Value {
         match self {
             Value::Null => false,
             Value::Num(x) => *x != 0.0,
+            Value::Int(x) => *x != 0,
+            Value::BigInt(x) => !x.is_zero(),
+            Value::Decimal(x) => !x.is_zero(),
             Value::Str(s) => !s.is_empty(),
             Value::Bool(b) => *b,
+            Value::Array(a) => !a.borrow().is_empty(),
+            Value::Map(m) => !m.borrow().is_empty(),
+            Value::Set(s) => !s.borrow().is_empty(),
             _ => true,
         }
     }
 
+    /// Whether two values are the very same array, map, set or function,
+    /// rather than merely equal. Other values are the same if they're equal.
+    pub fn is(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Set(a), Value::Set(b)) => Rc::ptr_eq(a, b),
+            (a, b) => a == b,
+        }
+    }
+
+    /// The element of an array or character of a string at index `i`, or the value
+    /// a map has at key `i`. Missing keys give null, but indices out of bounds are an error.
+    pub fn index(&self, i: &Value, allow_float: bool) -> Result<Value> {
+        let out_of_bounds = |pos: usize, len: usize| Error::OutOfBounds {
+            index: pos as f64,
+            len,
+        };
+        match self {
+            Value::Array(a) => {
+                let a = a.borrow();
+                let pos = as_index(i, allow_float)?;
+                a.get(pos)
+                    .cloned()
+                    .ok_or_else(|| out_of_bounds(pos, a.len()))
+            }
+            Value::Str(s) => {
+                let pos = as_index(i, allow_float)?;
+                match s.chars().nth(pos) {
+                    Some(c) => Ok(Value::new_str(c.to_string())),
+                    None => Err(out_of_bounds(pos, s.chars().count())),
+                }
+            }
+            Value::Map(m) => {
+                let key = Key::new(i.clone())?;
+                Ok(m.borrow().get(&key).cloned().unwrap_or(Value::Null))
+            }
+            val => Err(Error::WrongType {
+                expected: "Str, Array or Map",
+                found: val.clone(),
+            }),
+        }
+    }
+
+    /// Replace the element of an array at index `i`, which must already exist,
+    /// or set the value a map has at key `i`.
+    pub fn set_index(&self, i: &Value, val: Value, allow_float: bool) -> Result<()> {
+        match self {
+            Value::Array(a) => {
+                let mut a = a.borrow_mut()?;
+                let pos = as_index(i, allow_float)?;
+                let len = a.len();
+                let slot = a.get_mut(pos).ok_or(Error::OutOfBounds {
+                    index: pos as f64,
+                    len,
+                })?;
+                *slot = val;
+            }
+            Value::Map(m) => {
+                m.borrow_mut()?.insert(Key::new(i.clone())?, val);
+            }
+            val => {
+                return Err(Error::WrongType {
+                    expected: "Array or Map",
+                    found: val.clone(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// The address of an array, map or set, which only the very same one shares.
+    fn aggregate_ptr(&self) -> Option<*const ()> {
+        match self {
+            Value::Array(a) => Some(Rc::as_ptr(a) as _),
+            Value::Map(m) => Some(Rc::as_ptr(m) as _),
+            Value::Set(s) => Some(Rc::as_ptr(s) as _),
+            _ => None,
+        }
+    }
+
     pub fn type_name(&self) -> &'static str {
         match self {
             Value::Null => "Null",
             Value::Num(_) => "Num",
+            Value::Int(_) => "Int",
+            Value::BigInt(_) => "BigInt",
+            Value::Decimal(_) => "Decimal",
             Value::Str(_) => "Str",
             Value::Bool(_) => "Bool",
+            Value::Ok(_) | Value::Err(_) => "Result",
+            Value::Array(_) => "Array",
+            Value::Map(_) => "Map",
+            Value::Set(_) => "Set",
+            Value::Weak(_) => "Weak",
+            Value::Builder(_) => "StringBuilder",
             Value::Function { .. } => "Fn",
             Value::NativeFn { .. } => "NativeFn",
+            Value::Host { type_name, .. } => type_name,
+        }
+    }
+
+    /// Show the value like `Display` does, but with arrays, maps and sets nested more than
+    /// `depth` levels deep abbreviated, so that logging a value can't produce unbounded output.
+    pub fn display_depth(&self, depth: usize) -> DisplayDepth<'_> {
+        DisplayDepth { val: self, depth }
+    }
+
+    pub fn new_str<S: Into<String>>(s: S) -> Value {
+        Value::Str(Rc::new(s.into()))
+    }
+
+    pub fn new_array(vals: Vec<Value>) -> Value {
+        Value::Array(Shared::new(vals))
+    }
+
+    pub fn new_map() -> Value {
+        Value::new_map_from(IndexMap::new())
+    }
+
+    pub fn new_map_from(entries: IndexMap<Key, Value>) -> Value {
+        Value::Map(Shared::new(entries))
+    }
+
+    pub fn new_host<T: Any>(type_name: &'static str, object: T) -> Value {
+        Value::Host {
+            type_name,
+            object: Rc::new(object),
+        }
+    }
+
+    /// The object behind a host value, if it is of type `T`.
+    pub fn host_object<T: Any>(&self) -> Option<&T> {
+        match self {
+            Value::Host { object, .. } => object.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn new_set(vals: IndexSet<Key>) -> Value {
+        Value::Set(Shared::new(vals))
+    }
+
+    /// A deep copy of this value, in which every array, map and set is frozen.
+    /// Values which can't be modified in the first place are returned as they are.
+    /// Aggregates reachable more than once, including through a cycle, are copied once,
+    /// so the copy has the same shape as the original.
+    pub fn freeze(&self) -> Value {
+        self.freeze_in(&mut HashMap::new())
+    }
+
+    /// `copies` maps every aggregate frozen so far to its copy. A copy is recorded before its
+    /// elements are frozen, so that an aggregate containing itself ends up containing its copy.
+    fn freeze_in(&self, copies: &mut HashMap<*const (), Value>) -> Value {
+        if self.is_frozen() {
+            return self.clone();
+        }
+        let ptr = match self.aggregate_ptr() {
+            Some(ptr) => ptr,
+            None => return self.clone(),
+        };
+        if let Some(copy) = copies.get(&ptr) {
+            return copy.clone();
+        }
+        match self {
+            Value::Array(a) => {
+                let copy = Shared::frozen(Vec::new());
+                copies.insert(ptr, Value::Array(copy.clone()));
+                let vals = a.borrow().iter().map(|v| v.freeze_in(copies)).collect();
+                *copy.data.borrow_mut() = vals;
+                Value::Array(copy)
+            }
+            Value::Map(m) => {
+                let copy = Shared::frozen(IndexMap::new());
+                copies.insert(ptr, Value::Map(copy.clone()));
+                let entries = m
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.freeze_in(copies)))
+                    .collect();
+                *copy.data.borrow_mut() = entries;
+                Value::Map(copy)
+            }
+            Value::Set(s) => {
+                let copy = Value::Set(Shared::frozen(s.borrow().clone()));
+                copies.insert(ptr, copy.clone());
+                copy
+            }
+            val => val.clone(),
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        match self {
+            Value::Array(a) => a.is_frozen(),
+            Value::Map(m) => m.is_frozen(),
+            Value::Set(s) => s.is_frozen(),
+            Value::Builder(_) => false,
+            _ => true,
         }
     }
 
@@ -54,46 +458,344 @@ impl Value {
 
 impl Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Value::Null => write!(f, "null"),
-            Value::Num(x) => write!(f, "{}", x),
-            Value::Str(s) => write!(f, "{}", s),
-            Value::Bool(b) => write!(f, "{}", b),
-            Value::Function { name, .. } => {
-                write!(f, "fn {}", name.as_ref().map_or("(anonymous)", |x| &**x))
+        write_value(f, self, None, &mut Vec::new())
+    }
+}
+
+/// A value shown like `Display` does, but with aggregates nested too deeply abbreviated.
+pub struct DisplayDepth<'a> {
+    val: &'a Value,
+    depth: usize,
+}
+
+impl Display for DisplayDepth<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_value(f, self.val, Some(self.depth), &mut Vec::new())
+    }
+}
+
+/// Write `val` the way `print` shows it, abbreviating arrays, maps and sets
+/// as `[...]` or `{...}` once they're nested deeper than `depth`, if there's a limit.
+/// Those containing themselves are abbreviated the same way where they repeat,
+/// `seen` being the ones already being written further up.
+fn write_value(
+    f: &mut fmt::Formatter,
+    val: &Value,
+    depth: Option<usize>,
+    seen: &mut Vec<*const ()>,
+) -> fmt::Result {
+    let ptr = val.aggregate_ptr();
+    let abbreviated = depth == Some(0) || ptr.is_some_and(|ptr| seen.contains(&ptr));
+    let depth = depth.map(|depth| depth.saturating_sub(1));
+    if let (Some(ptr), false) = (ptr, abbreviated) {
+        seen.push(ptr);
+        let result = write_aggregate(f, val, depth, seen);
+        seen.pop();
+        return result;
+    }
+    match val {
+        Value::Null => write!(f, "null"),
+        Value::Num(x) => write!(f, "{}", x),
+        Value::Int(x) => write!(f, "{}", x),
+        Value::BigInt(x) => write!(f, "{}", x),
+        Value::Decimal(x) => write!(f, "{}", x),
+        Value::Str(s) => write!(f, "{}", s),
+        Value::Bool(b) => write!(f, "{}", b),
+        Value::Ok(val) => {
+            write!(f, "ok(")?;
+            write_element(f, val, depth, seen)?;
+            write!(f, ")")
+        }
+        Value::Err(err) => {
+            write!(f, "err(")?;
+            write_element(f, err, depth, seen)?;
+            write!(f, ")")
+        }
+        Value::Array(_) => write!(f, "[...]"),
+        Value::Map(_) | Value::Set(_) => write!(f, "{{...}}"),
+        Value::Weak(w) => match w.upgrade() {
+            // Don't print the target, which may well contain this reference.
+            Some(val) => write!(f, "weak {}", val.type_name()),
+            None => write!(f, "weak (dropped)"),
+        },
+        Value::Builder(b) => write!(f, "{}", b.borrow()),
+        Value::Function { chunk } => {
+            write!(f, "fn {}", chunk.name.as_deref().unwrap_or("(anonymous)"))
+        }
+        Value::NativeFn { name, .. } => match name {
+            Some(name) => write!(f, "native fn {}", name),
+            None => write!(f, "native fn"),
+        },
+        Value::Host { type_name, .. } => write!(f, "<{}>", type_name),
+    }
+}
+
+/// Write the elements of an array, map or set, already marked as being written in `seen`.
+fn write_aggregate(
+    f: &mut fmt::Formatter,
+    val: &Value,
+    depth: Option<usize>,
+    seen: &mut Vec<*const ()>,
+) -> fmt::Result {
+    match val {
+        Value::Array(a) => {
+            write!(f, "[")?;
+            for (i, val) in a.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_element(f, val, depth, seen)?;
+            }
+            write!(f, "]")
+        }
+        Value::Map(m) => {
+            write!(f, "{{")?;
+            for (i, (key, val)) in m.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_element(f, key.value(), depth, seen)?;
+                write!(f, ": ")?;
+                write_element(f, val, depth, seen)?;
+            }
+            write!(f, "}}")
+        }
+        Value::Set(s) => {
+            write!(f, "{{")?;
+            for (i, key) in s.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_element(f, key.value(), depth, seen)?;
             }
-            Value::NativeFn { .. } => write!(f, "native fn"),
+            write!(f, "}}")
         }
+        _ => unreachable!("only aggregates have elements"),
     }
 }
 
-impl Debug for Value {
+/// Write a value contained in an array or map, quoting strings
+/// so that they can be told apart from other values.
+fn write_element(
+    f: &mut fmt::Formatter,
+    val: &Value,
+    depth: Option<usize>,
+    seen: &mut Vec<*const ()>,
+) -> fmt::Result {
+    match val {
+        Value::Str(s) => write!(f, "{:?}", s),
+        val => write_value(f, val, depth, seen),
+    }
+}
+
+/// Characters of a string shown by `Debug` before the rest are left out.
+const MAX_DEBUG_CHARS: usize = 80;
+/// Elements of an array, map or set shown by `Debug` before the rest are left out.
+const MAX_DEBUG_ELEMENTS: usize = 16;
+/// How deeply `Debug` shows aggregates nested in each other, which also keeps it
+/// from going on forever on the ones containing themselves.
+const MAX_DEBUG_DEPTH: usize = 8;
+
+/// A value shown by `Debug`, with aggregates nested more than `depth` levels deep left out.
+struct DebugDepth<'a>(&'a Value, usize);
+
+impl Debug for DebugDepth<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
+        let DebugDepth(val, depth) = *self;
+        let elements = |f: &mut fmt::Formatter, name, vals: Vec<(Option<&Value>, &Value)>| {
+            if depth == 0 {
+                return write!(f, "{}(..)", name);
+            }
+            write!(f, "{}([", name)?;
+            for (i, (key, val)) in vals.iter().take(MAX_DEBUG_ELEMENTS).enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                if let Some(key) = key {
+                    write!(f, "{:?}: ", DebugDepth(key, depth - 1))?;
+                }
+                write!(f, "{:?}", DebugDepth(val, depth - 1))?;
+            }
+            if vals.len() > MAX_DEBUG_ELEMENTS {
+                write!(f, ", +{} more", vals.len() - MAX_DEBUG_ELEMENTS)?;
+            }
+            write!(f, "])")
+        };
+        match val {
             Value::Null => write!(f, "Null"),
             Value::Num(x) => write!(f, "Num({})", x),
-            Value::Str(s) => write!(f, "Str({})", s),
+            Value::Int(x) => write!(f, "Int({})", x),
+            Value::BigInt(x) => write!(f, "BigInt({})", x),
+            Value::Decimal(x) => write!(f, "Decimal({})", x),
+            Value::Str(s) => {
+                let len = s.chars().count();
+                if len > MAX_DEBUG_CHARS {
+                    let shown: String = s.chars().take(MAX_DEBUG_CHARS).collect();
+                    write!(f, "Str({:?} +{} chars)", shown, len - MAX_DEBUG_CHARS)
+                } else {
+                    write!(f, "Str({:?})", s)
+                }
+            }
             Value::Bool(b) => write!(f, "Bool({})", b),
-            Value::Function { chunk, name, arity } => write!(
-                f,
-                "Function {{ chunk = {:?}, name = {:?}, arity = {:?}, }}",
-                chunk, name, arity
-            ),
-            Value::NativeFn { .. } => write!(f, "NativeFn(..)"),
+            Value::Ok(val) => write!(f, "Ok({:?})", DebugDepth(val, depth)),
+            Value::Err(err) => write!(f, "Err({:?})", DebugDepth(err, depth)),
+            Value::Array(a) => elements(f, "Array", a.borrow().iter().map(|v| (None, v)).collect()),
+            Value::Map(m) => {
+                let m = m.borrow();
+                elements(
+                    f,
+                    "Map",
+                    m.iter().map(|(k, v)| (Some(k.value()), v)).collect(),
+                )
+            }
+            Value::Set(s) => {
+                let s = s.borrow();
+                elements(f, "Set", s.iter().map(|k| (None, k.value())).collect())
+            }
+            Value::Weak(w) => write!(f, "Weak({:?})", w.upgrade().map(|v| v.type_name())),
+            Value::Builder(b) => write!(f, "Builder({:?})", b.borrow()),
+            Value::Function { chunk } => write!(f, "Function({})", chunk),
+            Value::NativeFn { name, .. } => {
+                write!(
+                    f,
+                    "NativeFn({})",
+                    name.as_ref().map_or("..", |name| name.as_str())
+                )
+            }
+            Value::Host { type_name, .. } => write!(f, "Host({})", type_name),
         }
     }
 }
 
+impl Debug for Value {
+    /// Long strings and aggregates are cut short, so that logging a value stays readable.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", DebugDepth(self, MAX_DEBUG_DEPTH))
+    }
+}
+
+impl From<BigInt> for Value {
+    fn from(x: BigInt) -> Self {
+        Value::BigInt(Rc::new(x))
+    }
+}
+
+impl From<Decimal> for Value {
+    fn from(x: Decimal) -> Self {
+        Value::Decimal(Rc::new(x))
+    }
+}
+
+/// The operands of an arithmetic operation involving ints, big integers or decimals,
+/// converted to a common representation.
+enum Promoted {
+    Int(i64, i64),
+    Big(BigInt, BigInt),
+    Float(f64, f64),
+    Decimal(Decimal, Decimal),
+}
+
+impl Promoted {
+    /// Big integers combined with integral numbers stay exact,
+    /// while any fractional operand turns the operation into a floating-point one.
+    /// Ints combined with floats always give floats, and with big integers give big integers.
+    /// Decimals only mix with integers, since converting a float to a decimal isn't exact.
+    fn new(a: &Value, b: &Value) -> Option<Promoted> {
+        let big_to_f64 = |x: &BigInt| x.to_f64().unwrap_or(f64::NAN);
+        let to_decimal = |x: &Value| match x {
+            Value::Decimal(x) => Some((**x).clone()),
+            Value::Int(x) => Some(Decimal::from(BigInt::from(*x))),
+            Value::BigInt(x) => Some(Decimal::from((**x).clone())),
+            Value::Num(x) if x.fract() == 0.0 => BigInt::from_f64(*x).map(Decimal::from),
+            _ => None,
+        };
+        match (a, b) {
+            (Value::Decimal(_), _) | (_, Value::Decimal(_)) => {
+                Some(Promoted::Decimal(to_decimal(a)?, to_decimal(b)?))
+            }
+            (Value::Int(a), Value::Int(b)) => Some(Promoted::Int(*a, *b)),
+            (Value::Int(a), Value::Num(b)) => Some(Promoted::Float(*a as f64, *b)),
+            (Value::Num(a), Value::Int(b)) => Some(Promoted::Float(*a, *b as f64)),
+            (Value::Int(a), Value::BigInt(b)) => {
+                Some(Promoted::Big(BigInt::from(*a), (**b).clone()))
+            }
+            (Value::BigInt(a), Value::Int(b)) => {
+                Some(Promoted::Big((**a).clone(), BigInt::from(*b)))
+            }
+            (Value::BigInt(a), Value::BigInt(b)) => {
+                Some(Promoted::Big((**a).clone(), (**b).clone()))
+            }
+            (Value::BigInt(a), Value::Num(b)) if b.fract() == 0.0 => {
+                BigInt::from_f64(*b).map(|b| Promoted::Big((**a).clone(), b))
+            }
+            (Value::BigInt(a), Value::Num(b)) => Some(Promoted::Float(big_to_f64(a), *b)),
+            (Value::Num(_), Value::BigInt(_)) => match Promoted::new(b, a)? {
+                Promoted::Big(b, a) => Some(Promoted::Big(a, b)),
+                Promoted::Float(b, a) => Some(Promoted::Float(a, b)),
+                Promoted::Int(b, a) => Some(Promoted::Int(a, b)),
+                Promoted::Decimal(b, a) => Some(Promoted::Decimal(a, b)),
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Compute `op` on two ints, redoing it on big integers with `big_op` if it overflows.
+fn int_op(
+    a: i64,
+    b: i64,
+    op: fn(i64, i64) -> Option<i64>,
+    big_op: fn(BigInt, BigInt) -> BigInt,
+) -> Value {
+    match op(a, b) {
+        Some(x) => Value::Int(x),
+        None => big_op(BigInt::from(a), BigInt::from(b)).into(),
+    }
+}
+
+/// Compare an int to a float exactly, without rounding the int to the nearest float.
+fn cmp_int_float(a: i64, b: f64) -> Option<Ordering> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    if b.is_nan() {
+        None
+    } else if b >= LIMIT {
+        Some(Ordering::Less)
+    } else if b < -LIMIT {
+        Some(Ordering::Greater)
+    } else {
+        // Any float this small with a fractional part is well within range of an int.
+        Some(
+            a.cmp(&(b.trunc() as i64))
+                .then(0f64.partial_cmp(&b.fract())?),
+        )
+    }
+}
+
 impl Add<Value> for Value {
     type Output = Result<Value>;
 
     fn add(self, other: Value) -> Self::Output {
         match (self, other) {
             (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a + b)),
-            (Value::Str(a), Value::Str(b)) => Ok(Value::Str(format!("{}{}", a, b))),
-            (Value::Str(a), Value::Num(b)) => Ok(Value::Str(format!("{}{}", a, b))),
-            (Value::Str(a), Value::Bool(b)) => Ok(Value::Str(format!("{}{}", a, b))),
-            (a, b) => Err(Error::Binary { a, b, op: "+" }),
+            // Adding anything to a string concatenates it, the way `print` would show it.
+            (Value::Str(mut a), b) => {
+                // Temporaries (like the intermediate results of `a + b + c`)
+                // are uniquely owned, and can be appended to in place.
+                let s = Rc::make_mut(&mut a);
+                match b {
+                    Value::Str(b) => s.push_str(&b),
+                    b => s.push_str(&b.to_string()),
+                }
+                Ok(Value::Str(a))
+            }
+            (a, Value::Str(b)) => Ok(Value::new_str(format!("{}{}", a, b))),
+            (a, b) => match Promoted::new(&a, &b) {
+                Some(Promoted::Int(a, b)) => Ok(int_op(a, b, i64::checked_add, |a, b| a + b)),
+                Some(Promoted::Big(a, b)) => Ok((a + b).into()),
+                Some(Promoted::Float(a, b)) => Ok(Value::Num(a + b)),
+                Some(Promoted::Decimal(a, b)) => Ok(a.add(&b).into()),
+                None => Err(Error::Binary { a, b, op: "+" }),
+            },
         }
     }
 }
@@ -104,7 +806,17 @@ impl Sub<Value> for Value {
     fn sub(self, other: Value) -> Self::Output {
         match (self, other) {
             (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a - b)),
-            (a, b) => Err(Error::Binary { a, b, op: "-" }),
+            (Value::Set(a), Value::Set(b)) => {
+                let diff = a.borrow().difference(&b.borrow()).cloned().collect();
+                Ok(Value::new_set(diff))
+            }
+            (a, b) => match Promoted::new(&a, &b) {
+                Some(Promoted::Int(a, b)) => Ok(int_op(a, b, i64::checked_sub, |a, b| a - b)),
+                Some(Promoted::Big(a, b)) => Ok((a - b).into()),
+                Some(Promoted::Float(a, b)) => Ok(Value::Num(a - b)),
+                Some(Promoted::Decimal(a, b)) => Ok(a.sub(&b).into()),
+                None => Err(Error::Binary { a, b, op: "-" }),
+            },
         }
     }
 }
@@ -115,7 +827,13 @@ impl Mul<Value> for Value {
     fn mul(self, other: Value) -> Self::Output {
         match (self, other) {
             (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a * b)),
-            (a, b) => Err(Error::Binary { a, b, op: "*" }),
+            (a, b) => match Promoted::new(&a, &b) {
+                Some(Promoted::Int(a, b)) => Ok(int_op(a, b, i64::checked_mul, |a, b| a * b)),
+                Some(Promoted::Big(a, b)) => Ok((a * b).into()),
+                Some(Promoted::Float(a, b)) => Ok(Value::Num(a * b)),
+                Some(Promoted::Decimal(a, b)) => Ok(a.mul(&b).into()),
+                None => Err(Error::Binary { a, b, op: "*" }),
+            },
         }
     }
 }
@@ -126,7 +844,51 @@ impl Div<Value> for Value {
     fn div(self, other: Value) -> Self::Output {
         match (self, other) {
             (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a / b)),
-            (a, b) => Err(Error::Binary { a, b, op: "/" }),
+            // Division of big integers truncates, like integer division in Rust,
+            // while ints only divide exactly, and give a float otherwise.
+            (a, b) => match Promoted::new(&a, &b) {
+                Some(Promoted::Int(a, b)) => match a.checked_rem(b) {
+                    Some(0) => Ok(int_op(a, b, i64::checked_div, |a, b| a / b)),
+                    _ => Ok(Value::Num(a as f64 / b as f64)),
+                },
+                Some(Promoted::Big(_, b)) if b.is_zero() => Err(Error::DivisionByZero),
+                Some(Promoted::Big(a, b)) => Ok((a / b).into()),
+                Some(Promoted::Float(a, b)) => Ok(Value::Num(a / b)),
+                Some(Promoted::Decimal(a, b)) => {
+                    a.div(&b).map(Value::from).ok_or(Error::DivisionByZero)
+                }
+                None => Err(Error::Binary { a, b, op: "/" }),
+            },
+        }
+    }
+}
+
+impl BitOr<Value> for Value {
+    type Output = Result<Value>;
+
+    fn bitor(self, other: Value) -> Self::Output {
+        match (self, other) {
+            (Value::Set(a), Value::Set(b)) => {
+                let union = a.borrow().union(&b.borrow()).cloned().collect();
+                Ok(Value::new_set(union))
+            }
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a | b)),
+            (a, b) => Err(Error::Binary { a, b, op: "|" }),
+        }
+    }
+}
+
+impl BitAnd<Value> for Value {
+    type Output = Result<Value>;
+
+    fn bitand(self, other: Value) -> Self::Output {
+        match (self, other) {
+            (Value::Set(a), Value::Set(b)) => {
+                let intersection = a.borrow().intersection(&b.borrow()).cloned().collect();
+                Ok(Value::new_set(intersection))
+            }
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a & b)),
+            (a, b) => Err(Error::Binary { a, b, op: "&" }),
         }
     }
 }
@@ -137,6 +899,12 @@ impl Neg for Value {
     fn neg(self) -> Self::Output {
         match self {
             Value::Num(x) => Ok(Value::Num(-x)),
+            Value::Int(x) => Ok(match x.checked_neg() {
+                Some(x) => Value::Int(x),
+                None => (-BigInt::from(x)).into(),
+            }),
+            Value::BigInt(x) => Ok((-&*x).into()),
+            Value::Decimal(x) => Ok(x.neg().into()),
             x => Err(Error::Unary { x, op: "-" }),
         }
     }
@@ -152,12 +920,190 @@ impl Not for Value {
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Value::Num(a), Value::Num(b)) => a == b,
-            (Value::Str(a), Value::Str(b)) => a == b,
-            (Value::Bool(a), Value::Bool(b)) => a == b,
-            _ => false,
+        equals(self, other, &mut Vec::new())
+    }
+}
+
+/// Compare two values, where `seen` holds the pairs of arrays and maps being compared
+/// further up. Finding one of those again means they contain themselves, and they're
+/// taken to be equal there, since any difference shows up elsewhere.
+fn equals(a: &Value, b: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+    if let (Some(a_ptr), Some(b_ptr)) = (a.aggregate_ptr(), b.aggregate_ptr()) {
+        if a_ptr == b_ptr || seen.contains(&(a_ptr, b_ptr)) {
+            return true;
+        }
+        seen.push((a_ptr, b_ptr));
+        let equal = elements_equal(a, b, seen);
+        seen.pop();
+        return equal;
+    }
+    match (a, b) {
+        (Value::Null, Value::Null) => true,
+        (Value::Num(a), Value::Num(b)) => a == b,
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Int(a), Value::Num(b)) | (Value::Num(b), Value::Int(a)) => {
+            cmp_int_float(*a, *b) == Some(Ordering::Equal)
         }
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Ok(a), Value::Ok(b)) | (Value::Err(a), Value::Err(b)) => equals(a, b, seen),
+        (Value::Weak(a), Value::Weak(b)) => a.ptr_eq(b),
+        (Value::Builder(a), Value::Builder(b)) => Rc::ptr_eq(a, b),
+        (Value::Host { object: a, .. }, Value::Host { object: b, .. }) => Rc::ptr_eq(a, b),
+        // Functions are only equal to themselves, since there's no telling whether
+        // two different ones always do the same thing.
+        (Value::Function { chunk: a, .. }, Value::Function { chunk: b, .. }) => Rc::ptr_eq(a, b),
+        (Value::NativeFn { f: a, .. }, Value::NativeFn { f: b, .. }) => Rc::ptr_eq(a, b),
+        (a, b) => match Promoted::new(a, b) {
+            Some(Promoted::Int(a, b)) => a == b,
+            Some(Promoted::Big(a, b)) => a == b,
+            Some(Promoted::Float(a, b)) => a == b,
+            Some(Promoted::Decimal(a, b)) => a == b,
+            None => false,
+        },
+    }
+}
+
+/// Compare the elements of two different arrays, maps or sets.
+/// Maps are equal if they have the same entries, in any order.
+fn elements_equal(a: &Value, b: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+    match (a, b) {
+        (Value::Array(a), Value::Array(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equals(a, b, seen))
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| equals(a, b, seen)))
+        }
+        (Value::Set(a), Value::Set(b)) => *a.borrow() == *b.borrow(),
+        _ => false,
+    }
+}
+
+/// A value which can be used as a key of a map.
+/// Keys are null, numbers, strings, booleans, and frozen arrays of other keys:
+/// anything else could change while it's in the map, or has no sensible notion of equality.
+#[derive(Clone)]
+pub struct Key(Value);
+
+impl Key {
+    pub fn new(val: Value) -> Result<Key> {
+        Key::check(&val)?;
+        Ok(Key(val))
+    }
+
+    fn check(val: &Value) -> Result<()> {
+        Key::check_in(val, &mut Vec::new())
+    }
+
+    /// `path` holds the frozen arrays being checked, so an array containing itself is rejected
+    /// instead of checked forever.
+    fn check_in(val: &Value, path: &mut Vec<*const ()>) -> Result<()> {
+        match val {
+            Value::Null
+            | Value::Num(_)
+            | Value::Int(_)
+            | Value::BigInt(_)
+            | Value::Decimal(_)
+            | Value::Str(_)
+            | Value::Bool(_) => Ok(()),
+            Value::Array(a) if a.is_frozen() => {
+                let ptr = Rc::as_ptr(a) as *const ();
+                if path.contains(&ptr) {
+                    return Err(Error::Unhashable(val.clone()));
+                }
+                path.push(ptr);
+                let checked = a.borrow().iter().try_for_each(|v| Key::check_in(v, path));
+                path.pop();
+                checked
+            }
+            val => Err(Error::Unhashable(val.clone())),
+        }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+
+    /// Numbers are compared by their bit pattern, with both zeroes treated as the same key.
+    fn num_bits(x: f64) -> u64 {
+        if x == 0.0 {
+            0
+        } else {
+            x.to_bits()
+        }
+    }
+
+    /// Ints and floats holding the same whole number are the same key,
+    /// so that `1` and the `1` returned by `len` find the same entry.
+    fn as_int(val: &Value) -> Option<i64> {
+        const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+        match val {
+            Value::Int(x) => Some(*x),
+            Value::Num(x) if x.fract() == 0.0 && (-LIMIT..LIMIT).contains(x) => Some(*x as i64),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        fn eq(a: &Value, b: &Value) -> bool {
+            if let (Some(a), Some(b)) = (Key::as_int(a), Key::as_int(b)) {
+                return a == b;
+            }
+            match (a, b) {
+                (Value::Null, Value::Null) => true,
+                (Value::Num(a), Value::Num(b)) => Key::num_bits(*a) == Key::num_bits(*b),
+                (Value::Array(a), Value::Array(b)) => {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| eq(a, b))
+                }
+                // Keys of different types are distinct, even if the values compare equal.
+                (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b) && a == b,
+            }
+        }
+        eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        fn hash<H: Hasher>(val: &Value, state: &mut H) {
+            if let Some(x) = Key::as_int(val) {
+                std::mem::discriminant(&Value::Int(0)).hash(state);
+                x.hash(state);
+                return;
+            }
+            std::mem::discriminant(val).hash(state);
+            match val {
+                Value::Num(x) => Key::num_bits(*x).hash(state),
+                Value::BigInt(x) => x.hash(state),
+                Value::Decimal(x) => x.hash(state),
+                Value::Str(s) => s.hash(state),
+                Value::Bool(b) => b.hash(state),
+                Value::Array(a) => {
+                    let a = a.borrow();
+                    a.len().hash(state);
+                    for val in a.iter() {
+                        hash(val, state);
+                    }
+                }
+                _ => (),
+            }
+        }
+        hash(&self.0, state)
+    }
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0)
     }
 }
 
@@ -165,9 +1111,17 @@ impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
             (Value::Num(a), Value::Num(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Num(b)) => cmp_int_float(*a, *b),
+            (Value::Num(a), Value::Int(b)) => cmp_int_float(*b, *a).map(Ordering::reverse),
             (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
             (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
-            _ => None,
+            (a, b) => match Promoted::new(a, b)? {
+                Promoted::Int(a, b) => a.partial_cmp(&b),
+                Promoted::Big(a, b) => a.partial_cmp(&b),
+                Promoted::Float(a, b) => a.partial_cmp(&b),
+                Promoted::Decimal(a, b) => a.partial_cmp(&b),
+            },
         }
     }
 }
@@ -188,6 +1142,20 @@ pub enum Error {
         b: Value,
     },
     WrongCall(Value),
+    WrongType {
+        expected: &'static str,
+        found: Value,
+    },
+    Unhashable(Value),
+    OutOfBounds {
+        index: f64,
+        len: usize,
+    },
+    DivisionByZero,
+    Frozen,
+    InvalidArgument(String),
+    /// A number with a fractional part used where only whole numbers make sense.
+    NotWhole(f64),
 }
 
 impl Display for Error {
@@ -217,6 +1185,34 @@ impl Display for Error {
                 "Cannot call value of type {} like a function",
                 val.type_name()
             ),
+            Error::WrongType { expected, found } => write!(
+                f,
+                "Expected {}, found value of type '{}'",
+                expected,
+                found.type_name()
+            ),
+            Error::Unhashable(Value::Array(a)) if a.is_frozen() => write!(
+                f,
+                "Arrays containing themselves can't be used as a map key or set element"
+            ),
+            Error::Unhashable(Value::Array(_)) => write!(
+                f,
+                "Arrays can only be used as a map key or set element once they're frozen"
+            ),
+            Error::Unhashable(val) => write!(
+                f,
+                "Value of type '{}' cannot be used as a map key or set element",
+                val.type_name()
+            ),
+            Error::OutOfBounds { index, len } => write!(
+                f,
+                "Index {} out of bounds for array of length {}",
+                index, len
+            ),
+            Error::DivisionByZero => write!(f, "Division by zero"),
+            Error::Frozen => write!(f, "Cannot modify a frozen value"),
+            Error::InvalidArgument(msg) => write!(f, "{}", msg),
+            Error::NotWhole(x) => write!(f, "Expected a whole number, found {}", x),
         }
     }
 }
@@ -0,0 +1,135 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use crate::vm::{Instruction, Value};
+
+/// The globals a single function (or the top-level script) reads and writes.
+struct Symbols {
+    label: String,
+    function: Option<String>,
+    defines: BTreeSet<String>,
+    uses: BTreeSet<String>,
+}
+
+/// A cross-reference of every function in a program and the globals it refers to.
+pub struct CallGraph {
+    symbols: Vec<Symbols>,
+}
+
+impl CallGraph {
+    /// Build the call graph of the script `chunk` and its benchmarks.
+    pub fn new(chunk: &[Instruction], benches: &[(String, Value)]) -> Self {
+        let mut graph = CallGraph {
+            symbols: Vec::new(),
+        };
+        graph.collect("<script>".to_owned(), None, chunk);
+        for (name, bench) in benches {
+            if let Value::Function { chunk, .. } = bench {
+                graph.collect(format!("bench \"{}\"", name), None, &chunk.code);
+            }
+        }
+        graph
+    }
+
+    fn collect(&mut self, label: String, function: Option<String>, chunk: &[Instruction]) {
+        let mut symbols = Symbols {
+            label,
+            function,
+            defines: BTreeSet::new(),
+            uses: BTreeSet::new(),
+        };
+        let mut nested = Vec::new();
+        for instr in chunk {
+            match instr {
+                Instruction::GetGlobal(name) => {
+                    symbols.uses.insert(name.clone());
+                }
+                Instruction::SetGlobal(name) | Instruction::DefineGlobal(name) => {
+                    symbols.defines.insert(name.clone());
+                }
+                Instruction::Push(f @ Value::Function { .. }) => nested.push(f),
+                _ => (),
+            }
+        }
+        self.symbols.push(symbols);
+        for f in nested {
+            if let Value::Function { chunk } = f {
+                self.collect(f.to_string(), chunk.name.clone(), &chunk.code);
+            }
+        }
+    }
+
+    /// Named functions which are never referenced from outside their own body.
+    fn dead_functions(&self) -> Vec<&str> {
+        self.symbols
+            .iter()
+            .filter_map(|s| s.function.as_deref())
+            .filter(|name| {
+                self.symbols
+                    .iter()
+                    .filter(|other| other.function.as_deref() != Some(name))
+                    .all(|other| !other.uses.contains(*name))
+            })
+            .collect()
+    }
+}
+
+/// The names of the modules imported by `chunk` or the functions it declares,
+/// when they're given as a string literal.
+pub fn imports(chunk: &[Instruction]) -> Vec<String> {
+    let mut names = Vec::new();
+    for window in chunk.windows(3) {
+        if let [Instruction::GetGlobal(callee), Instruction::Push(Value::Str(name)), Instruction::Call(1)] =
+            window
+        {
+            if callee == "import" {
+                names.push(name.to_string());
+            }
+        }
+    }
+    for instr in chunk {
+        if let Instruction::Push(Value::Function { chunk, .. }) = instr {
+            names.extend(imports(&chunk.code));
+        }
+    }
+    names
+}
+
+/// The functions declared at the top level of `chunk`, along with the globals storing them.
+pub fn functions(chunk: &[Instruction]) -> Vec<(String, Value)> {
+    let mut functions = Vec::new();
+    for window in chunk.windows(2) {
+        if let [Instruction::Push(f @ Value::Function { chunk }), Instruction::DefineGlobal(global)] =
+            window
+        {
+            if chunk.name.as_ref() == Some(global) {
+                functions.push((global.clone(), f.clone()));
+            }
+        }
+    }
+    functions
+}
+
+fn write_names(f: &mut fmt::Formatter, label: &str, names: &BTreeSet<String>) -> fmt::Result {
+    if !names.is_empty() {
+        let names: Vec<_> = names.iter().map(String::as_str).collect();
+        writeln!(f, "    {:8} {}", label, names.join(", "))?;
+    }
+    Ok(())
+}
+
+impl Display for CallGraph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for symbols in &self.symbols {
+            writeln!(f, "{}", symbols.label)?;
+            write_names(f, "defines", &symbols.defines)?;
+            write_names(f, "uses", &symbols.uses)?;
+        }
+        let dead = self.dead_functions();
+        if dead.is_empty() {
+            write!(f, "No unreferenced functions")
+        } else {
+            write!(f, "Unreferenced functions: {}", dead.join(", "))
+        }
+    }
+}
@@ -0,0 +1,30 @@
+// Executables made by `oxide bundle` run their script with the arguments they're given.
+use std::env;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn passes_arguments_to_the_bundled_script() {
+    let dir = env::temp_dir().join(format!("oxide-bundle-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("failed to create a directory for the test");
+    let script = dir.join("args.o2");
+    let tool = dir.join("tool");
+    fs::write(&script, "print(args)\n").expect("failed to write the script");
+
+    let bundled = Command::new(env!("CARGO_BIN_EXE_oxide"))
+        .arg("bundle")
+        .arg(&script)
+        .arg("-o")
+        .arg(&tool)
+        .status()
+        .expect("failed to run oxide bundle");
+    assert!(bundled.success());
+    let output = Command::new(&tool)
+        .args(["x", "y"])
+        .output()
+        .expect("failed to run the bundled executable");
+    let _ = fs::remove_dir_all(&dir);
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[\"x\", \"y\"]\n");
+}
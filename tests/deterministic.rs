@@ -0,0 +1,37 @@
+// Runs with --deterministic should print exactly the same thing every time.
+use std::io::Write as _;
+use std::process::{Command, Output, Stdio};
+
+const SCRIPT: &str = r#"
+log_info("starting")
+print(temp_file())
+print(temp_dir())
+log_info("done")
+"#;
+
+fn run(args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_oxide"))
+        .args(args)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start oxide");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(SCRIPT.as_bytes())
+        .expect("failed to write the script");
+    child.wait_with_output().expect("failed to wait for oxide")
+}
+
+#[test]
+fn repeats_logs_and_temporary_names() {
+    let first = run(&["--deterministic"]);
+    let second = run(&["--deterministic"]);
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+    assert_eq!(first.stderr, second.stderr);
+}
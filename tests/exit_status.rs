@@ -0,0 +1,55 @@
+// Scripts which fail, or aren't run at all, should make oxide exit with a failure status.
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+
+/// Run `source` through `oxide [args...] -`, returning whether it exited successfully.
+fn run(args: &[&str], source: &str) -> bool {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_oxide"))
+        .args(args)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to start oxide");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(source.as_bytes())
+        .expect("failed to write the script");
+    child.wait().expect("failed to wait for oxide").success()
+}
+
+#[test]
+fn succeeds_when_the_script_finishes() {
+    assert!(run(&[], "print(1 + 2)\n"));
+}
+
+#[test]
+fn fails_on_a_compilation_error() {
+    assert!(!run(&[], "let = 1\n"));
+}
+
+#[test]
+fn fails_on_a_runtime_error() {
+    assert!(!run(&[], "print(undefined_name)\n"));
+}
+
+#[test]
+fn fails_on_a_denied_warning() {
+    let source = "fn ignore(x) -> 0\nprint(ignore(1))\n";
+    assert!(run(&[], source));
+    assert!(!run(&["--deny-warnings"], source));
+}
+
+#[test]
+fn fails_on_an_error_in_a_script_file() {
+    assert!(!Command::new(env!("CARGO_BIN_EXE_oxide"))
+        .arg("tests/freeze.o2")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("failed to run oxide")
+        .success());
+}
@@ -0,0 +1,169 @@
+// Runs every script in tests/ and checks what it prints against the comments in it,
+// and replays the REPL transcripts in tests/transcripts.
+//
+// A comment at the end of a line of code is a line the script should print by then.
+// So is each comment on the lines right below a line of code without one, for output
+// spanning several lines. Comments starting with `Runtime error:` or `Compilation error:`
+// give the error the script should stop with, along with the lines of comments below them,
+// and comments starting with `warning[` a warning it should report. Scripts without any
+// of these are only examples, and aren't checked.
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Stands for a line the script prints with nothing on it.
+const EMPTY_LINE: &str = "(an empty line)";
+
+#[derive(Default)]
+struct Expected {
+    output: Vec<String>,
+    warnings: Vec<String>,
+    error: Vec<String>,
+}
+
+impl Expected {
+    fn is_empty(&self) -> bool {
+        self.output.is_empty() && self.warnings.is_empty() && self.error.is_empty()
+    }
+
+    fn push(&mut self, text: &str) {
+        let starts_error =
+            text.starts_with("Runtime error:") || text.starts_with("Compilation error:");
+        if starts_error || !self.error.is_empty() {
+            self.error.push(text.to_owned());
+        } else if text.starts_with("warning[") {
+            self.warnings.push(text.to_owned());
+        } else if text == EMPTY_LINE {
+            self.output.push(String::new());
+        } else {
+            self.output.push(text.to_owned());
+        }
+    }
+}
+
+/// The text of the comment ending `line`, if there is one outside of its string literals.
+fn trailing_comment(line: &str) -> Option<&str> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '/' if !in_string && line[i..].starts_with("//") => {
+                return Some(line[i + 2..].trim_start());
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+fn expectations(source: &str) -> Expected {
+    let mut expected = Expected::default();
+    // Whether comments on the following lines are output of the code above them.
+    let mut in_block = false;
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        if let Some(comment) = trimmed.strip_prefix("//") {
+            if in_block {
+                expected.push(comment.strip_prefix(' ').unwrap_or(comment));
+            }
+        } else if trimmed.is_empty() {
+            in_block = false;
+        } else if let Some(comment) = trailing_comment(line) {
+            expected.push(comment);
+            in_block = false;
+        } else {
+            in_block = true;
+        }
+    }
+    expected
+}
+
+/// Run the script at `path`, describing how its output differs from what's expected, if it does.
+fn check(path: &Path) -> Option<String> {
+    let source = fs::read_to_string(path).expect("failed to read the script");
+    let expected = expectations(&source);
+    if expected.is_empty() {
+        return None;
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_oxide"))
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .expect("failed to run oxide");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let printed: Vec<_> = stdout.lines().collect();
+    if printed != expected.output {
+        return Some(format!(
+            "expected output:\n{}\nfound:\n{}",
+            expected.output.join("\n"),
+            stdout
+        ));
+    }
+    let reported: Vec<_> = stderr.lines().collect();
+    for warning in &expected.warnings {
+        if !reported.contains(&warning.as_str()) {
+            return Some(format!(
+                "expected the warning {}, found:\n{}",
+                warning, stderr
+            ));
+        }
+    }
+    let error = expected.error.join("\n");
+    match (expected.error.is_empty(), output.status.success()) {
+        (true, true) => None,
+        (true, false) => Some(format!("expected no error, found:\n{}", stderr)),
+        (false, true) => Some(format!(
+            "expected the error:\n{}\nbut the script succeeded",
+            error
+        )),
+        (false, false) if stderr.trim_end().ends_with(&error) => None,
+        (false, false) => Some(format!(
+            "expected the error:\n{}\nfound:\n{}",
+            error, stderr
+        )),
+    }
+}
+
+#[test]
+fn scripts_print_what_their_comments_say() {
+    let mut paths: Vec<_> = fs::read_dir("tests")
+        .expect("failed to list the tests")
+        .map(|entry| entry.expect("failed to list the tests").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "o2"))
+        .collect();
+    paths.sort();
+    let failures: Vec<_> = paths
+        .iter()
+        .filter_map(|path| Some(format!("{}: {}", path.display(), check(path)?)))
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} scripts failed:\n\n{}",
+        failures.len(),
+        paths.len(),
+        failures.join("\n\n")
+    );
+}
+
+#[test]
+fn transcripts_replay_unchanged() {
+    for entry in fs::read_dir("tests/transcripts").expect("failed to list the transcripts") {
+        let path = entry.expect("failed to list the transcripts").path();
+        let output = Command::new(env!("CARGO_BIN_EXE_oxide"))
+            .arg("transcript")
+            .arg(&path)
+            .output()
+            .expect("failed to run oxide");
+        assert!(
+            output.status.success(),
+            "{} changed:\n{}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}